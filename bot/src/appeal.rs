@@ -0,0 +1,553 @@
+//! Appeals against sanctions
+//!
+//! Sanction notifications come with a button that opens a modal. The
+//! submitted appeal is stored in the database and posted to the appeal channel
+//! of the guild, where the staff can accept or deny it.
+
+use poise::serenity_prelude::{
+    ButtonStyle, CacheHttp, Color, Context, CreateButton, CreateEmbed, EditMember, InputTextStyle,
+    InteractionResponseType, Message, MessageComponentInteraction, ModalSubmitInteraction,
+    Permissions, UserId,
+};
+use tracing::Level;
+
+use crate::{
+    modlog::{Action, Case, Entry, ModLog},
+    util::{is_discord_error, modal_text, UNKNOWN_BAN, UNKNOWN_MEMBER},
+    Data, Result,
+};
+
+/// Button on a sanction notification that opens the appeal modal
+const APPEAL: &str = "appeal";
+/// Modal a user submits an appeal with
+const APPEAL_SUBMIT: &str = "appeal-submit";
+/// Button that accepts an appeal
+const ACCEPT: &str = "appeal-accept";
+/// Button that opens the modal to deny an appeal
+const DENY: &str = "appeal-deny";
+/// Modal the staff submits the response to a denied appeal with
+const DENY_SUBMIT: &str = "appeal-deny-submit";
+
+/// A stored appeal
+struct Appeal {
+    id: i64,
+    case: Case,
+    status: String,
+}
+
+impl Appeal {
+    async fn fetch(data: &Data, id: i64) -> Result<Self> {
+        let row = sqlx::query!(r#"SELECT "case", status FROM appeals WHERE id = $1"#, id)
+            .fetch_optional(&data.pool)
+            .await?
+            .ok_or_else(|| anyhow!("Appeal #{} doesn't exist.", id))?;
+        let case = Case::fetch(&data.pool, row.case)
+            .await?
+            .ok_or_else(|| anyhow!("Case #{} doesn't exist.", row.case))?;
+        Ok(Self {
+            id,
+            case,
+            status: row.status,
+        })
+    }
+
+    /// Decide on a pending appeal
+    ///
+    /// Fails if the appeal was decided in the meantime, so only one member of
+    /// the staff can decide on it.
+    async fn claim(
+        data: &Data,
+        id: i64,
+        status: &str,
+        staff: UserId,
+        response: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query_scalar!(
+            r#"
+        UPDATE appeals SET status = $2, staff = $3, response = $4, decided_at = now()
+        WHERE id = $1 AND status = 'pending'
+        RETURNING id
+        "#,
+            id,
+            status,
+            staff.0.to_string(),
+            response,
+        )
+        .fetch_optional(&data.pool)
+        .await?
+        .ok_or_else(|| anyhow!("Appeal #{} has already been decided.", id))?;
+        Ok(())
+    }
+
+    /// Make a claimed appeal pending again
+    async fn unclaim(data: &Data, id: i64) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"
+        UPDATE appeals SET status = 'pending', staff = NULL, response = NULL, decided_at = NULL
+        WHERE id = $1
+        "#,
+            id,
+        )
+        .execute(&data.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Make sure that nobody decided on this appeal yet and that a member with
+    /// `permissions` is allowed to decide on it
+    fn check_decidable(&self, permissions: Option<Permissions>) -> Result<()> {
+        if self.status != "pending" {
+            return Err(anyhow!("Appeal #{} has already been {}.", self.id, self.status).into());
+        }
+        let required = match self.case.entry.action {
+            Action::Timeout => Permissions::MODERATE_MEMBERS,
+            Action::Ban => Permissions::BAN_MEMBERS,
+            _ => Permissions::KICK_MEMBERS,
+        };
+        match permissions {
+            Some(permissions) if permissions.contains(required) => Ok(()),
+            _ => Err(anyhow!("You don't have the permission to decide on this appeal.").into()),
+        }
+    }
+}
+
+pub struct Appeals;
+
+impl Appeals {
+    /// The button that lets a user appeal `case`
+    pub fn button(case: i64) -> CreateButton {
+        let mut button = CreateButton::default();
+        button
+            .custom_id(format!("{}:{}", APPEAL, case))
+            .label("Appeal")
+            .style(ButtonStyle::Secondary);
+        button
+    }
+
+    /// Whether a component or modal with this id belongs to an appeal
+    pub fn handles(custom_id: &str) -> bool {
+        custom_id.starts_with(APPEAL)
+    }
+
+    #[instrument(skip_all, name = "appeal_component", level = "debug")]
+    pub async fn on_component(
+        ctx: &Context,
+        data: &Data,
+        interaction: &MessageComponentInteraction,
+    ) {
+        let (kind, id) = match parse_custom_id(&interaction.data.custom_id) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        let result = match kind {
+            APPEAL => Self::open_appeal(ctx, data, interaction, id).await,
+            ACCEPT => Self::accept(ctx, data, interaction, id).await,
+            DENY => Self::open_deny(ctx, data, interaction, id).await,
+            _ => return,
+        };
+        if let Err(e) = result {
+            event!(Level::WARN, error = ?e, "failed to handle appeal component {}: {}", interaction.data.custom_id, e);
+            if let Err(e) = interaction
+                .create_interaction_response(ctx.http(), |r| {
+                    r.interaction_response_data(|d| d.content(e.to_string()).ephemeral(true))
+                })
+                .await
+            {
+                event!(Level::WARN, error = ?e, "failed to respond to appeal component: {}", e);
+            }
+        }
+    }
+
+    #[instrument(skip_all, name = "appeal_modal", level = "debug")]
+    pub async fn on_modal(ctx: &Context, data: &Data, interaction: &ModalSubmitInteraction) {
+        let (kind, id) = match parse_custom_id(&interaction.data.custom_id) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        let result = match kind {
+            APPEAL_SUBMIT => Self::submit(ctx, data, interaction, id).await,
+            DENY_SUBMIT => Self::deny(ctx, data, interaction, id).await,
+            _ => return,
+        };
+        if let Err(e) = result {
+            event!(Level::WARN, error = ?e, "failed to handle appeal modal {}: {}", interaction.data.custom_id, e);
+            if let Err(e) = interaction
+                .create_interaction_response(ctx.http(), |r| {
+                    r.interaction_response_data(|d| d.content(e.to_string()).ephemeral(true))
+                })
+                .await
+            {
+                event!(Level::WARN, error = ?e, "failed to respond to appeal modal: {}", e);
+            }
+        }
+    }
+
+    /// Show the appeal modal to the sanctioned user
+    async fn open_appeal(
+        ctx: &Context,
+        data: &Data,
+        interaction: &MessageComponentInteraction,
+        case: i64,
+    ) -> Result<()> {
+        let case = Case::fetch(&data.pool, case)
+            .await?
+            .ok_or_else(|| anyhow!("This case doesn't exist anymore."))?;
+        if case.entry.target != interaction.user.id {
+            return Err(anyhow!("You can only appeal your own sanctions.").into());
+        }
+        if sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM appeals WHERE "case" = $1) AS "exists!""#,
+            case.id
+        )
+        .fetch_one(&data.pool)
+        .await?
+        {
+            return Err(anyhow!("You've already appealed this sanction.").into());
+        }
+
+        interaction
+            .create_interaction_response(ctx.http(), |r| {
+                r.kind(InteractionResponseType::Modal)
+                    .interaction_response_data(|d| {
+                        d.custom_id(format!("{}:{}", APPEAL_SUBMIT, case.id))
+                            .title(format!("Appeal case #{}", case.id))
+                            .components(|c| {
+                                c.create_action_row(|row| {
+                                    row.create_input_text(|t| {
+                                        t.custom_id("appeal")
+                                            .label("Why should this sanction be lifted?")
+                                            .style(InputTextStyle::Paragraph)
+                                            .min_length(10)
+                                            .max_length(1000)
+                                            .required(true)
+                                    })
+                                })
+                            })
+                    })
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Store a submitted appeal and post it to the staff
+    async fn submit(
+        ctx: &Context,
+        data: &Data,
+        interaction: &ModalSubmitInteraction,
+        case: i64,
+    ) -> Result<()> {
+        let content = modal_text(&interaction.data, "appeal")
+            .ok_or_else(|| anyhow!("Your appeal can't be empty."))?;
+        let case = Case::fetch(&data.pool, case)
+            .await?
+            .ok_or_else(|| anyhow!("This case doesn't exist anymore."))?;
+        if case.entry.target != interaction.user.id {
+            return Err(anyhow!("You can only appeal your own sanctions.").into());
+        }
+        let channel = data
+            .guild(case.entry.guild)
+            .appeals
+            .ok_or_else(|| anyhow!("This server doesn't accept appeals."))?;
+
+        let id = sqlx::query_scalar!(
+            r#"
+        INSERT INTO appeals ("case", "user", content)
+        VALUES ($1, $2, $3)
+        ON CONFLICT ("case") DO NOTHING
+        RETURNING id
+        "#,
+            case.id,
+            interaction.user.id.0.to_string(),
+            content,
+        )
+        .fetch_optional(&data.pool)
+        .await?
+        .ok_or_else(|| anyhow!("You've already appealed this sanction."))?;
+        event!(
+            Level::INFO,
+            appeal = id,
+            case = case.id,
+            user = interaction.user.id.0,
+            "user {} appealed case {}",
+            interaction.user.id.0,
+            case.id
+        );
+
+        channel
+            .send_message(ctx.http(), |m| {
+                m.embed(|e| {
+                    e.title(format!("Appeal #{}", id));
+                    e.color(Color::BLUE);
+                    e.description(&content);
+                    e.field(
+                        "User",
+                        format!("<@{}> ({})", interaction.user.id, interaction.user.id),
+                        true,
+                    );
+                    e.field(
+                        "Case",
+                        match case.link() {
+                            Some(link) => format!("[#{}]({})", case.id, link),
+                            None => format!("#{}", case.id),
+                        },
+                        true,
+                    );
+                    e.field("Action", case.entry.action, true);
+                    e.field(
+                        "Reason",
+                        case.entry.reason.as_deref().unwrap_or("No reason given"),
+                        false,
+                    );
+                    e
+                });
+                m.components(|c| {
+                    c.create_action_row(|r| {
+                        r.create_button(|b| {
+                            b.custom_id(format!("{}:{}", ACCEPT, id))
+                                .label("Accept")
+                                .style(ButtonStyle::Success)
+                        })
+                        .create_button(|b| {
+                            b.custom_id(format!("{}:{}", DENY, id))
+                                .label("Deny")
+                                .style(ButtonStyle::Danger)
+                        })
+                    })
+                })
+            })
+            .await?;
+
+        interaction
+            .create_interaction_response(ctx.http(), |r| {
+                r.interaction_response_data(|d| {
+                    d.content(
+                        "Your appeal has been submitted. You'll be notified once the staff \
+                         decided on it.",
+                    )
+                    .ephemeral(true)
+                })
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Accept an appeal and reverse the penalty of the case
+    async fn accept(
+        ctx: &Context,
+        data: &Data,
+        interaction: &MessageComponentInteraction,
+        id: i64,
+    ) -> Result<()> {
+        let appeal = Appeal::fetch(data, id).await?;
+        appeal.check_decidable(interaction.member.as_ref().and_then(|m| m.permissions))?;
+        let entry = &appeal.case.entry;
+
+        // claim the appeal first, so it can't be decided twice if two members
+        // of the staff click at the same time
+        Appeal::claim(data, id, "accepted", interaction.user.id, None).await?;
+
+        let (reversal, outcome, result) = match entry.action {
+            Action::Timeout => (
+                Some(Action::RemoveTimeout),
+                "Your timeout has been lifted.",
+                entry
+                    .guild
+                    .edit_member(ctx.http(), entry.target, EditMember::enable_communication)
+                    .await
+                    // a member who left can't be timed out anymore
+                    .err()
+                    .filter(|e| !is_discord_error(e, UNKNOWN_MEMBER)),
+            ),
+            Action::Ban => (
+                Some(Action::Unban),
+                "You've been unbanned.",
+                entry
+                    .guild
+                    .unban(ctx.http(), entry.target)
+                    .await
+                    .err()
+                    .filter(|e| !is_discord_error(e, UNKNOWN_BAN)),
+            ),
+            _ => (
+                None,
+                "The staff agrees that this sanction wasn't justified.",
+                None,
+            ),
+        };
+        if let Some(e) = result {
+            // give the appeal back, so it can be accepted again
+            if let Err(e) = Appeal::unclaim(data, id).await {
+                event!(Level::ERROR, appeal = id, error = ?e, "failed to reset appeal {}: {}", id, e);
+            }
+            return Err(e.into());
+        }
+        event!(
+            Level::INFO,
+            appeal = id,
+            staff = interaction.user.id.0,
+            "appeal {} accepted by {}",
+            id,
+            interaction.user.id.0
+        );
+
+        let embed = decided_embed(
+            &interaction.message,
+            Color::DARK_GREEN,
+            format!("Accepted by <@{}>", interaction.user.id),
+        );
+        interaction
+            .create_interaction_response(ctx.http(), |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| d.set_embed(embed).components(|c| c))
+            })
+            .await?;
+
+        if let Some(reversal) = reversal {
+            ModLog::log(
+                ctx,
+                data,
+                Entry::new(entry.guild, reversal, entry.target, interaction.user.id)
+                    .reason(format!("Appeal #{} accepted", id)),
+            )
+            .await;
+        }
+
+        notify(
+            ctx,
+            entry.target,
+            "Your appeal has been accepted",
+            Color::DARK_GREEN,
+            format!(
+                "Your appeal against case #{} on **{}** has been accepted.\n{}",
+                appeal.case.id,
+                entry.guild.name(ctx).unwrap_or_default(),
+                outcome
+            ),
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Ask the staff for the response to a denied appeal
+    async fn open_deny(
+        ctx: &Context,
+        data: &Data,
+        interaction: &MessageComponentInteraction,
+        id: i64,
+    ) -> Result<()> {
+        Appeal::fetch(data, id)
+            .await?
+            .check_decidable(interaction.member.as_ref().and_then(|m| m.permissions))?;
+
+        interaction
+            .create_interaction_response(ctx.http(), |r| {
+                r.kind(InteractionResponseType::Modal)
+                    .interaction_response_data(|d| {
+                        d.custom_id(format!("{}:{}", DENY_SUBMIT, id))
+                            .title(format!("Deny appeal #{}", id))
+                            .components(|c| {
+                                c.create_action_row(|row| {
+                                    row.create_input_text(|t| {
+                                        t.custom_id("response")
+                                            .label("Response to the user")
+                                            .style(InputTextStyle::Paragraph)
+                                            .max_length(1000)
+                                            .required(true)
+                                    })
+                                })
+                            })
+                    })
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Deny an appeal and send the response of the staff to the user
+    async fn deny(
+        ctx: &Context,
+        data: &Data,
+        interaction: &ModalSubmitInteraction,
+        id: i64,
+    ) -> Result<()> {
+        let response = modal_text(&interaction.data, "response")
+            .ok_or_else(|| anyhow!("The response can't be empty."))?;
+        let appeal = Appeal::fetch(data, id).await?;
+        appeal.check_decidable(interaction.member.as_ref().and_then(|m| m.permissions))?;
+        Appeal::claim(data, id, "denied", interaction.user.id, Some(&response)).await?;
+        event!(
+            Level::INFO,
+            appeal = id,
+            staff = interaction.user.id.0,
+            "appeal {} denied by {}",
+            id,
+            interaction.user.id.0
+        );
+
+        let message = interaction
+            .message
+            .as_ref()
+            .ok_or_else(|| anyhow!("The appeal message is missing."))?;
+        let mut embed = decided_embed(
+            message,
+            Color::RED,
+            format!("Denied by <@{}>", interaction.user.id),
+        );
+        embed.field("Response", &response, false);
+        interaction
+            .create_interaction_response(ctx.http(), |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| d.set_embed(embed).components(|c| c))
+            })
+            .await?;
+
+        notify(
+            ctx,
+            appeal.case.entry.target,
+            "Your appeal has been denied",
+            Color::RED,
+            format!(
+                "Your appeal against case #{} on **{}** has been denied.\n\n{}",
+                appeal.case.id,
+                appeal.case.entry.guild.name(ctx).unwrap_or_default(),
+                response
+            ),
+        )
+        .await;
+        Ok(())
+    }
+}
+
+/// Split a custom id like `appeal-accept:42` into its kind and id
+fn parse_custom_id(custom_id: &str) -> Option<(&str, i64)> {
+    let (kind, id) = custom_id.split_once(':')?;
+    Some((kind, id.parse().ok()?))
+}
+
+/// Copy the embed of an appeal message and add the decision of the staff
+fn decided_embed(message: &Message, color: Color, decision: String) -> CreateEmbed {
+    let mut embed = message
+        .embeds
+        .first()
+        .cloned()
+        .map(CreateEmbed::from)
+        .unwrap_or_default();
+    embed.color(color);
+    embed.field("Decision", decision, false);
+    embed
+}
+
+/// Send the decision on an appeal to the user. Failures are only logged.
+async fn notify(ctx: &Context, user: UserId, title: &str, color: Color, description: String) {
+    let result = match user.create_dm_channel(ctx).await {
+        Ok(channel) => channel
+            .send_message(ctx.http(), |m| {
+                m.embed(|e| e.title(title).color(color).description(description))
+            })
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        event!(Level::WARN, user = user.0, error = ?e, "failed to notify user {} about appeal: {}", user.0, e);
+    }
+}
@@ -0,0 +1,282 @@
+//! Audit log
+//!
+//! Joins, leaves, role changes and bans are posted to the audit log channel of
+//! the guild. If Discord's own audit log knows who is responsible for a
+//! change, the moderator is included.
+
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Utc};
+use poise::serenity_prelude::{
+    CacheHttp, Color, Context, CreateEmbed, GuildId, Member, RoleId, User, UserId,
+};
+use serenity::model::guild::audit_log::{Action as AuditLogAction, MemberAction};
+use tracing::Level;
+
+use crate::{invite::Attribution, util::format_duration, Data};
+
+/// Discord writes its audit log entries shortly after the gateway event is
+/// sent
+const AUDIT_LOG_DELAY: StdDuration = StdDuration::from_secs(2);
+
+/// The moderator responsible for a change and their reason
+struct Responsible {
+    moderator: UserId,
+    reason: Option<String>,
+}
+
+pub struct AuditLog;
+
+impl AuditLog {
+    /// Post a new member with the age of their account and their invite
+    #[instrument(skip_all, name = "audit_log_join", level = "debug")]
+    pub async fn on_join(
+        ctx: &Context,
+        data: &Data,
+        member: &Member,
+        attribution: Option<&Attribution>,
+    ) {
+        let user = &member.user;
+        let created = user.id.created_at();
+        let age = Utc::now() - *created;
+        Self::post(ctx, data, member.guild_id, |e| {
+            e.title("Member joined");
+            e.color(Color::DARK_GREEN);
+            e.thumbnail(user.face());
+            e.description(format!("<@{}> {}", user.id, user.tag()));
+            e.field(
+                "Account age",
+                format!(
+                    "{} (created <t:{}:f>)",
+                    format_duration(Duration::minutes(age.num_minutes())),
+                    created.unix_timestamp()
+                ),
+                false,
+            );
+            e.field(
+                "Invite",
+                match attribution {
+                    Some(attribution) => format!(
+                        "`{}` from <@{}>",
+                        attribution.code, attribution.invite.inviter
+                    ),
+                    None if user.bot => "Added by an integration".to_string(),
+                    None => "Unknown".to_string(),
+                },
+                false,
+            );
+            e.footer(|f| f.text(format!("User {}", user.id)));
+            e.timestamp(Utc::now())
+        })
+        .await;
+    }
+
+    /// Post a member who left or was kicked
+    ///
+    /// Banned members are left out, since their ban is posted by
+    /// [`AuditLog::on_ban`].
+    #[instrument(skip_all, name = "audit_log_leave", level = "debug")]
+    pub async fn on_leave(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        user: &User,
+        member: Option<&Member>,
+    ) {
+        if data.guild(guild).auditlog.is_none() {
+            return;
+        }
+        let (ban, kick) = tokio::join!(
+            Self::responsible(ctx, guild, MemberAction::BanAdd, user.id),
+            Self::responsible(ctx, guild, MemberAction::Kick, user.id),
+        );
+        if ban.is_some() {
+            return;
+        }
+        Self::post(ctx, data, guild, |e| {
+            e.color(Color::ORANGE);
+            e.thumbnail(user.face());
+            e.description(format!("<@{}> {}", user.id, user.tag()));
+            match &kick {
+                Some(kick) => {
+                    e.title("Member kicked");
+                    responsible_fields(e, kick);
+                }
+                None => {
+                    e.title("Member left");
+                }
+            }
+            if let Some(member) = member {
+                if let Some(joined) = member.joined_at {
+                    e.field("Joined", format!("<t:{}:R>", joined.unix_timestamp()), true);
+                }
+                if !member.roles.is_empty() {
+                    e.field("Roles", format_roles(&member.roles), false);
+                }
+            }
+            e.footer(|f| f.text(format!("User {}", user.id)));
+            e.timestamp(Utc::now())
+        })
+        .await;
+    }
+
+    /// Post the roles a member got or lost
+    ///
+    /// Nothing is posted if the member wasn't cached, since there is nothing
+    /// to compare with.
+    #[instrument(skip_all, name = "audit_log_update", level = "debug")]
+    pub async fn on_update(ctx: &Context, data: &Data, old: Option<&Member>, new: &Member) {
+        let guild = new.guild_id;
+        let old = match old {
+            Some(old) if data.guild(guild).auditlog.is_some() => old,
+            _ => return,
+        };
+        let added = new
+            .roles
+            .iter()
+            .copied()
+            .filter(|r| !old.roles.contains(r))
+            .collect::<Vec<_>>();
+        let removed = old
+            .roles
+            .iter()
+            .copied()
+            .filter(|r| !new.roles.contains(r))
+            .collect::<Vec<_>>();
+        if added.is_empty() && removed.is_empty() {
+            return;
+        }
+
+        let responsible =
+            Self::responsible(ctx, guild, MemberAction::RoleUpdate, new.user.id).await;
+        Self::post(ctx, data, guild, |e| {
+            e.title("Roles changed");
+            e.color(Color::BLURPLE);
+            e.description(format!("<@{}> {}", new.user.id, new.user.tag()));
+            if !added.is_empty() {
+                e.field("Added", format_roles(&added), false);
+            }
+            if !removed.is_empty() {
+                e.field("Removed", format_roles(&removed), false);
+            }
+            if let Some(responsible) = &responsible {
+                responsible_fields(e, responsible);
+            }
+            e.footer(|f| f.text(format!("User {}", new.user.id)));
+            e.timestamp(Utc::now())
+        })
+        .await;
+    }
+
+    #[instrument(skip_all, name = "audit_log_ban", level = "debug")]
+    pub async fn on_ban(ctx: &Context, data: &Data, guild: GuildId, user: &User) {
+        Self::on_ban_change(ctx, data, guild, user, MemberAction::BanAdd).await;
+    }
+
+    #[instrument(skip_all, name = "audit_log_unban", level = "debug")]
+    pub async fn on_unban(ctx: &Context, data: &Data, guild: GuildId, user: &User) {
+        Self::on_ban_change(ctx, data, guild, user, MemberAction::BanRemove).await;
+    }
+
+    async fn on_ban_change(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        user: &User,
+        action: MemberAction,
+    ) {
+        if data.guild(guild).auditlog.is_none() {
+            return;
+        }
+        let responsible = Self::responsible(ctx, guild, action, user.id).await;
+        Self::post(ctx, data, guild, |e| {
+            match action {
+                MemberAction::BanAdd => e.title("Member banned").color(Color::RED),
+                _ => e.title("Member unbanned").color(Color::DARK_GREEN),
+            };
+            e.thumbnail(user.face());
+            e.description(format!("<@{}> {}", user.id, user.tag()));
+            if let Some(responsible) = &responsible {
+                responsible_fields(e, responsible);
+            }
+            e.footer(|f| f.text(format!("User {}", user.id)));
+            e.timestamp(Utc::now())
+        })
+        .await;
+    }
+
+    /// Find the moderator who just did `action` to `target` in the audit log
+    /// of the guild
+    async fn responsible(
+        ctx: &Context,
+        guild: GuildId,
+        action: MemberAction,
+        target: UserId,
+    ) -> Option<Responsible> {
+        tokio::time::sleep(AUDIT_LOG_DELAY).await;
+        let logs = match guild
+            .audit_logs(
+                ctx.http(),
+                Some(AuditLogAction::Member(action).num()),
+                None,
+                None,
+                Some(10),
+            )
+            .await
+        {
+            Ok(logs) => logs,
+            Err(e) => {
+                event!(Level::DEBUG, guild = guild.0, error = ?e, "cannot read the audit log of guild {}: {}", guild.0, e);
+                return None;
+            }
+        };
+        let since = Utc::now() - Duration::seconds(30);
+        logs.entries
+            .into_iter()
+            .find(|entry| entry.target_id == Some(target.0) && *entry.id.created_at() > since)
+            .map(|entry| Responsible {
+                moderator: entry.user_id,
+                reason: entry.reason,
+            })
+    }
+
+    async fn post<F>(ctx: &Context, data: &Data, guild: GuildId, f: F)
+    where
+        F: FnOnce(&mut CreateEmbed) -> &mut CreateEmbed,
+    {
+        let channel = match data.guild(guild).auditlog {
+            Some(channel) => channel,
+            None => return,
+        };
+        if let Err(e) = channel
+            .send_message(ctx.http(), |m| {
+                m.embed(f);
+                m.allowed_mentions(|a| a.empty_parse())
+            })
+            .await
+        {
+            event!(Level::WARN, guild = guild.0, error = ?e, "failed to post to the audit log of guild {}: {}", guild.0, e);
+        }
+    }
+}
+
+fn responsible_fields(e: &mut CreateEmbed, responsible: &Responsible) {
+    e.field("Moderator", format!("<@{}>", responsible.moderator), true);
+    if let Some(reason) = &responsible.reason {
+        e.field("Reason", reason, true);
+    }
+}
+
+/// Mention the roles, as many as fit into an embed field
+fn format_roles(roles: &[RoleId]) -> String {
+    let mut s = String::new();
+    for role in roles {
+        let mention = format!("<@&{}> ", role);
+        if s.len() + mention.len() > 1000 {
+            s.push('…');
+            break;
+        }
+        s.push_str(&mention);
+    }
+    s.trim_end().to_string()
+}
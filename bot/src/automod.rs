@@ -0,0 +1,410 @@
+//! Automatic moderation
+//!
+//! Every rule checks new messages on its own. Rules are configured per guild
+//! in the `automod` section of the guild config, each with its own action and
+//! exemptions. A rule is only active if it has a section, except for the
+//! sticker rule, which is active by default.
+//!
+//! To add a rule, implement [`AutomodRule`] for its [`Rule`] and add it to
+//! [`AutomodConfig`].
+
+use std::collections::HashSet;
+
+use chrono::{Duration, Utc};
+use poise::serenity_prelude::{CacheHttp, ChannelId, Context, GuildId, Message, RoleId, UserId};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use sqlx::PgPool;
+use tracing::Level;
+
+use crate::{
+    modlog::{Action, Entry, ModLog},
+    util::{ban_with_notification, send_sanction_notification, Penalty},
+    Data,
+};
+
+mod filter;
+mod links;
+mod spam;
+mod sticker;
+
+pub use filter::{normalize, Filter, FilterKind, FilterOptions, FilterStore};
+pub use links::{InviteOptions, LinkOptions};
+pub use spam::{SpamOptions, SpamTracker};
+pub use sticker::StickerOptions;
+
+/// The longest timeout Discord allows
+const MAX_TIMEOUT: i64 = 28 * 24 * 60 * 60;
+
+/// What happens if a message breaks a rule
+///
+/// The name of the choice is also used to store the action in the database.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+#[serde(rename_all = "lowercase")]
+pub enum AutomodAction {
+    /// The message is deleted
+    #[name = "delete"]
+    Delete,
+    /// The message is deleted and the sender is warned
+    #[name = "warn"]
+    Warn,
+    /// The message is deleted and the sender is timed out
+    #[name = "timeout"]
+    Timeout,
+    /// The message is deleted and the sender is banned
+    #[name = "ban"]
+    Ban,
+}
+
+/// Settings every rule has
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct RuleSettings {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Defaults to [`AutomodRule::default_action`]
+    #[serde(default)]
+    pub action: Option<AutomodAction>,
+    /// Length of the timeout in seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: i64,
+    /// Every offence within `window` multiplies the timeout by this factor
+    #[serde(default = "default_escalation")]
+    pub escalation: f64,
+    /// How long offences count towards the escalation, in seconds
+    #[serde(default = "default_window")]
+    pub window: i64,
+    /// Members with any of these roles are not checked
+    #[serde_as(as = "HashSet<DisplayFromStr>")]
+    #[serde(default, rename = "exemptroles")]
+    pub exempt_roles: HashSet<RoleId>,
+    /// Messages in these channels are not checked
+    #[serde_as(as = "HashSet<DisplayFromStr>")]
+    #[serde(default, rename = "exemptchannels")]
+    pub exempt_channels: HashSet<ChannelId>,
+}
+
+impl Default for RuleSettings {
+    /// The settings of a rule that isn't configured, which disable it
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            action: None,
+            timeout: default_timeout(),
+            escalation: default_escalation(),
+            window: default_window(),
+            exempt_roles: HashSet::new(),
+            exempt_channels: HashSet::new(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_timeout() -> i64 {
+    60
+}
+
+fn default_escalation() -> f64 {
+    1.0
+}
+
+fn default_window() -> i64 {
+    24 * 60 * 60
+}
+
+impl RuleSettings {
+    /// Whether `message` is exempt from the rule
+    pub fn exempt(&self, message: &Message) -> bool {
+        self.exempt_channels.contains(&message.channel_id)
+            || message
+                .member
+                .as_ref()
+                .map(|m| m.roles.iter().any(|r| self.exempt_roles.contains(r)))
+                .unwrap_or(false)
+    }
+
+    /// The timeout for someone with `offences` previous offences
+    pub fn escalated_timeout(&self, offences: i64) -> Duration {
+        let seconds = self.timeout as f64 * self.escalation.powi(offences as i32);
+        Duration::seconds((seconds as i64).clamp(0, MAX_TIMEOUT))
+    }
+}
+
+/// The config of a single rule: the common settings and the options of the
+/// rule itself
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Rule<T> {
+    #[serde(flatten)]
+    pub settings: RuleSettings,
+    #[serde(flatten)]
+    pub options: T,
+}
+
+/// The automod rules of a guild
+#[derive(Debug, Deserialize, Clone)]
+pub struct AutomodConfig {
+    #[serde(default = "default_sticker")]
+    pub sticker: Rule<StickerOptions>,
+    #[serde(default)]
+    pub spam: Rule<SpamOptions>,
+    #[serde(default)]
+    pub invites: Rule<InviteOptions>,
+    #[serde(default)]
+    pub links: Rule<LinkOptions>,
+    #[serde(default = "default_filter")]
+    pub filter: Rule<FilterOptions>,
+}
+
+impl Default for AutomodConfig {
+    fn default() -> Self {
+        Self {
+            sticker: default_sticker(),
+            spam: Rule::default(),
+            invites: Rule::default(),
+            links: Rule::default(),
+            filter: default_filter(),
+        }
+    }
+}
+
+fn default_sticker() -> Rule<StickerOptions> {
+    Rule {
+        settings: RuleSettings {
+            enabled: true,
+            ..RuleSettings::default()
+        },
+        options: StickerOptions::default(),
+    }
+}
+
+/// The filters are managed with `/filter`, so the rule is active as soon as
+/// there are any
+fn default_filter() -> Rule<FilterOptions> {
+    Rule {
+        settings: RuleSettings {
+            enabled: true,
+            ..RuleSettings::default()
+        },
+        options: FilterOptions::default(),
+    }
+}
+
+impl AutomodConfig {
+    /// All enabled rules, in the order they are checked
+    pub fn rules(&self) -> Vec<&dyn AutomodRule> {
+        let rules: [&dyn AutomodRule; 5] = [
+            &self.sticker,
+            &self.spam,
+            &self.invites,
+            &self.links,
+            &self.filter,
+        ];
+        rules
+            .into_iter()
+            .filter(|rule| rule.settings().enabled)
+            .collect()
+    }
+}
+
+/// Why a message broke a rule
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// Shown to the member and in the mod log, e.g. "sending a default
+    /// sticker"
+    pub reason: String,
+    /// Overrides the action of the rule
+    pub action: Option<AutomodAction>,
+}
+
+impl Violation {
+    pub fn new(reason: impl ToString) -> Self {
+        Self {
+            reason: reason.to_string(),
+            action: None,
+        }
+    }
+
+    pub fn action(mut self, action: AutomodAction) -> Self {
+        self.action = Some(action);
+        self
+    }
+}
+
+#[async_trait]
+pub trait AutomodRule: Send + Sync {
+    /// The name offences against this rule are stored as
+    fn name(&self) -> &'static str;
+
+    fn settings(&self) -> &RuleSettings;
+
+    /// The action that is taken if the config of the rule doesn't set one
+    fn default_action(&self) -> AutomodAction {
+        AutomodAction::Delete
+    }
+
+    /// Check a message that isn't exempt from this rule
+    async fn check(&self, ctx: &Context, data: &Data, message: &Message) -> Option<Violation>;
+}
+
+pub struct Automod;
+
+impl Automod {
+    /// Check a new message against the rules of its guild and punish the
+    /// sender for the first rule it breaks
+    #[instrument(skip_all, name = "automod", level = "debug")]
+    pub async fn on_message(ctx: &Context, data: &Data, message: &Message) {
+        let guild = match message.guild_id {
+            Some(guild) if !message.author.bot => guild,
+            _ => return,
+        };
+        let config = data.guild(guild).automod;
+        for rule in config.rules() {
+            if rule.settings().exempt(message) {
+                continue;
+            }
+            if let Some(violation) = rule.check(ctx, data, message).await {
+                Self::enforce(ctx, data, guild, message, rule, violation).await;
+                return;
+            }
+        }
+    }
+
+    async fn enforce(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        message: &Message,
+        rule: &dyn AutomodRule,
+        violation: Violation,
+    ) {
+        let settings = rule.settings();
+        let action = violation
+            .action
+            .or(settings.action)
+            .unwrap_or_else(|| rule.default_action());
+        let member = message.author.id;
+        // offences during maintenance don't count towards the escalation
+        if data.in_maintenance(guild) {
+            data.maintenance
+                .defer(
+                    &data.pool,
+                    guild,
+                    member,
+                    action.name(),
+                    &format!("{}: {}", rule.name(), violation.reason),
+                )
+                .await;
+            return;
+        }
+
+        let offences = match record_offence(&data.pool, guild, member, rule.name(), settings.window)
+            .await
+        {
+            Ok(offences) => offences,
+            Err(e) => {
+                event!(Level::WARN, rule = rule.name(), error = ?e, "failed to record {} offence: {}", rule.name(), e);
+                0
+            }
+        };
+        event!(
+            Level::INFO,
+            member = member.0,
+            guild = guild.0,
+            rule = rule.name(),
+            offences,
+            "member {} broke rule {} ({} previous offences): {}",
+            member.0,
+            rule.name(),
+            offences,
+            violation.reason
+        );
+
+        if let Err(e) = message.delete(ctx).await {
+            event!(Level::WARN, message = message.id.0, error = ?e, "failed to delete message {}: {}", message.id.0, e);
+        }
+
+        let moderator = ctx.cache.current_user_id();
+        let (entry, penalty, fallback) = match action {
+            AutomodAction::Delete => return,
+            AutomodAction::Warn => (
+                Entry::new(guild, Action::Warn, member, moderator),
+                Penalty::Warning,
+                Some(message.channel_id),
+            ),
+            AutomodAction::Timeout => {
+                let until = Utc::now() + settings.escalated_timeout(offences);
+                if let Err(e) = guild
+                    .edit_member(ctx.http(), member, |m| {
+                        m.disable_communication_until_datetime(until.into())
+                    })
+                    .await
+                {
+                    event!(Level::WARN, member = member.0, error = ?e, "failed to timeout member {}: {}", member.0, e);
+                    return;
+                }
+                (
+                    Entry::new(guild, Action::Timeout, member, moderator).until(until),
+                    Penalty::Timeout(until),
+                    Some(message.channel_id),
+                )
+            }
+            AutomodAction::Ban => {
+                let entry =
+                    Entry::new(guild, Action::Ban, member, moderator).reason(&violation.reason);
+                if let Err(e) = ban_with_notification(ctx, data, &message.author, entry, 0).await {
+                    event!(Level::WARN, member = member.0, error = ?e, "failed to ban member {}: {}", member.0, e);
+                }
+                return;
+            }
+        };
+
+        let case = ModLog::log(ctx, data, entry.reason(&violation.reason)).await;
+        send_sanction_notification(
+            ctx,
+            data,
+            guild,
+            &message.author,
+            &violation.reason,
+            penalty,
+            case.as_ref(),
+            fallback,
+        )
+        .await;
+    }
+}
+
+/// Store an offence against `rule` and return the number of previous offences
+/// of the user against it within the last `window` seconds
+pub async fn record_offence(
+    pool: &PgPool,
+    guild: GuildId,
+    user: UserId,
+    rule: &str,
+    window: i64,
+) -> sqlx::Result<i64> {
+    let since = Utc::now() - Duration::seconds(window);
+    let previous = sqlx::query_scalar!(
+        r#"
+    SELECT COUNT(*) AS "count!" FROM offences
+    WHERE guild = $1 AND "user" = $2 AND rule = $3 AND created_at > $4
+    "#,
+        guild.0.to_string(),
+        user.0.to_string(),
+        rule,
+        since,
+    )
+    .fetch_one(pool)
+    .await?;
+    sqlx::query!(
+        r#"INSERT INTO offences (guild, "user", rule) VALUES ($1, $2, $3)"#,
+        guild.0.to_string(),
+        user.0.to_string(),
+        rule,
+    )
+    .execute(pool)
+    .await?;
+    Ok(previous)
+}
@@ -0,0 +1,338 @@
+//! Content filter
+//!
+//! Guilds add regular expressions and word lists with `/filter`. They are
+//! stored in the database and compiled once per guild; the compiled filters
+//! are reloaded whenever the filters of a guild change.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use poise::serenity_prelude::{ChannelId, Context, GuildId, Message};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::Level;
+
+use super::{AutomodAction, AutomodRule, Rule, RuleSettings, Violation};
+use crate::{util::channel_scopes, Data};
+
+/// Limits how large a compiled pattern can get, so a single pattern can't
+/// exhaust the memory of the bot
+const SIZE_LIMIT: usize = 1 << 20;
+
+/// The filter rule has no options of its own, the filters are stored in the
+/// database
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FilterOptions {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum FilterKind {
+    /// A regular expression, matched against the message as it was sent and
+    /// against its normalized form
+    #[name = "regex"]
+    Regex,
+    /// Comma separated words, matched against the normalized message
+    #[name = "words"]
+    Words,
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub id: i64,
+    pub kind: FilterKind,
+    pub pattern: String,
+    pub action: AutomodAction,
+    pub reason: String,
+    /// The channel or category this filter is limited to
+    pub scope: Option<ChannelId>,
+    regex: Regex,
+}
+
+impl Filter {
+    /// Check whether `pattern` is valid and compile it
+    pub fn compile(kind: FilterKind, pattern: &str) -> anyhow::Result<Regex> {
+        let pattern = match kind {
+            FilterKind::Regex => pattern.to_string(),
+            FilterKind::Words => {
+                let words = pattern
+                    .split(',')
+                    .map(|w| regex::escape(normalize(w).trim()))
+                    .filter(|w| !w.is_empty())
+                    .collect::<Vec<_>>();
+                if words.is_empty() {
+                    return Err(anyhow!("the word list is empty"));
+                }
+                format!(r"\b(?:{})\b", words.join("|"))
+            }
+        };
+        Ok(RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .size_limit(SIZE_LIMIT)
+            .build()?)
+    }
+
+    /// Whether a message with `content` matches this filter. `normalized` is
+    /// `content` after [`normalize`].
+    pub fn matches(&self, content: &str, normalized: &str) -> bool {
+        match self.kind {
+            FilterKind::Regex => self.regex.is_match(content) || self.regex.is_match(normalized),
+            FilterKind::Words => self.regex.is_match(normalized),
+        }
+    }
+}
+
+/// The compiled filters of every guild that were used since they changed
+#[derive(Debug, Clone, Default)]
+pub struct FilterStore(Arc<RwLock<Compiled>>);
+
+#[derive(Debug, Default)]
+struct Compiled {
+    filters: HashMap<GuildId, Arc<Vec<Filter>>>,
+    /// How often the filters of a guild were invalidated, so filters that
+    /// were loaded before an invalidation aren't cached
+    generations: HashMap<GuildId, u64>,
+}
+
+impl FilterStore {
+    /// The filters of `guild`, loaded from the database if they aren't cached
+    pub async fn get(&self, pool: &PgPool, guild: GuildId) -> sqlx::Result<Arc<Vec<Filter>>> {
+        let generation = {
+            let compiled = self.0.read().unwrap();
+            if let Some(filters) = compiled.filters.get(&guild) {
+                return Ok(filters.clone());
+            }
+            compiled
+                .generations
+                .get(&guild)
+                .copied()
+                .unwrap_or_default()
+        };
+
+        let filters = Arc::new(load(pool, guild).await?);
+        let mut compiled = self.0.write().unwrap();
+        if compiled
+            .generations
+            .get(&guild)
+            .copied()
+            .unwrap_or_default()
+            == generation
+        {
+            compiled.filters.insert(guild, filters.clone());
+        }
+        Ok(filters)
+    }
+
+    /// Forget the filters of `guild`, so they are reloaded on the next message
+    pub fn invalidate(&self, guild: GuildId) {
+        let mut compiled = self.0.write().unwrap();
+        compiled.filters.remove(&guild);
+        *compiled.generations.entry(guild).or_default() += 1;
+    }
+}
+
+/// Load and compile the filters of a guild. Filters that can't be loaded are
+/// skipped.
+async fn load(pool: &PgPool, guild: GuildId) -> sqlx::Result<Vec<Filter>> {
+    let rows = sqlx::query!(
+        r#"SELECT id, kind, pattern, action, reason, scope FROM filters WHERE guild = $1 ORDER BY id"#,
+        guild.0.to_string()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let filter = (|| -> anyhow::Result<Filter> {
+                let kind = row.kind.parse().map_err(|e| anyhow!("{:?}", e))?;
+                Ok(Filter {
+                    id: row.id,
+                    kind,
+                    regex: Filter::compile(kind, &row.pattern)?,
+                    pattern: row.pattern,
+                    action: row.action.parse().map_err(|e| anyhow!("{:?}", e))?,
+                    reason: row.reason,
+                    scope: row.scope.map(|s| s.parse()).transpose()?,
+                })
+            })();
+            match filter {
+                Ok(filter) => Some(filter),
+                Err(e) => {
+                    event!(Level::WARN, filter = row.id, guild = guild.0, error = ?e, "skipping invalid filter {}: {}", row.id, e);
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+/// Undo common tricks to get around filters
+///
+/// The text is lowercased, invisible characters are removed and look-alike
+/// characters, accented letters and leetspeak are replaced by the plain
+/// letters they stand for.
+pub fn normalize(content: &str) -> String {
+    content
+        .to_lowercase()
+        .chars()
+        .filter(|c| {
+            !matches!(
+                c,
+                '\u{200b}'..='\u{200f}' | '\u{2060}' | '\u{feff}' | '\u{ad}'
+            )
+        })
+        .map(|c| match c {
+            // fullwidth forms
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0)
+                .unwrap_or(c)
+                .to_ascii_lowercase(),
+            c => c,
+        })
+        .map(|c| match c {
+            // leetspeak
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '|' => 'l',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' | '+' => 't',
+            '8' => 'b',
+            '9' => 'g',
+            // cyrillic and greek look-alikes
+            'а' | 'α' => 'a',
+            'е' | 'ё' | 'ε' => 'e',
+            'і' | 'ї' | 'ι' => 'i',
+            'о' | 'ο' => 'o',
+            'р' | 'ρ' => 'p',
+            'с' => 'c',
+            'у' => 'y',
+            'х' | 'χ' => 'x',
+            'к' | 'κ' => 'k',
+            'м' => 'm',
+            'т' | 'τ' => 't',
+            'ѕ' => 's',
+            'ј' => 'j',
+            'ԁ' => 'd',
+            'ɡ' => 'g',
+            'ν' => 'v',
+            'υ' => 'u',
+            // accented letters
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ý' | 'ÿ' => 'y',
+            'ñ' => 'n',
+            'ç' => 'c',
+            c => c,
+        })
+        .collect()
+}
+
+#[async_trait]
+impl AutomodRule for Rule<FilterOptions> {
+    fn name(&self) -> &'static str {
+        "filter"
+    }
+
+    fn settings(&self) -> &RuleSettings {
+        &self.settings
+    }
+
+    async fn check(&self, ctx: &Context, data: &Data, message: &Message) -> Option<Violation> {
+        let guild = message.guild_id?;
+        let filters = match data.filters.get(&data.pool, guild).await {
+            Ok(filters) => filters,
+            Err(e) => {
+                event!(Level::WARN, guild = guild.0, error = ?e, "failed to load filters of guild {}: {}", guild.0, e);
+                return None;
+            }
+        };
+        if filters.is_empty() || message.content.is_empty() {
+            return None;
+        }
+
+        let scopes = channel_scopes(ctx, message.channel_id);
+        let normalized = normalize(&message.content);
+        filters
+            .iter()
+            .filter(|f| f.scope.map(|s| scopes.contains(&s)).unwrap_or(true))
+            .find(|f| f.matches(&message.content, &normalized))
+            .map(|f| Violation::new(&f.reason).action(f.action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_leetspeak() {
+        assert_eq!(normalize("H3LL0 W0RLD"), "hello world");
+        assert_eq!(normalize("fr33 n!7r0"), "free nitro");
+        assert_eq!(normalize("$p@m"), "spam");
+    }
+
+    #[test]
+    fn normalize_look_alikes() {
+        // cyrillic а, е and о
+        assert_eq!(normalize("sc\u{430}m"), "scam");
+        assert_eq!(normalize("fr\u{435}\u{435} r\u{43e}bux"), "free robux");
+        // fullwidth letters
+        assert_eq!(normalize("ＳＣＡＭ"), "scam");
+        assert_eq!(normalize("çàfé"), "cafe");
+    }
+
+    #[test]
+    fn normalize_invisible_characters() {
+        assert_eq!(normalize("sc\u{200b}a\u{ad}m\u{feff}"), "scam");
+    }
+
+    #[test]
+    fn words_match_whole_words() {
+        let regex = Filter::compile(FilterKind::Words, "scam, free nitro").unwrap();
+        assert!(regex.is_match(&normalize("this is a SC4M")));
+        assert!(regex.is_match(&normalize("get fr33 n1tro here")));
+        assert!(!regex.is_match(&normalize("scampi for dinner")));
+        assert!(!regex.is_match(&normalize("a free nitrogen tank")));
+    }
+
+    #[test]
+    fn words_are_normalized() {
+        let regex = Filter::compile(FilterKind::Words, "SC4M").unwrap();
+        assert!(regex.is_match("scam"));
+    }
+
+    #[test]
+    fn words_cant_be_empty() {
+        assert!(Filter::compile(FilterKind::Words, " , ,").is_err());
+    }
+
+    #[test]
+    fn words_are_escaped() {
+        let regex = Filter::compile(FilterKind::Words, "a.b").unwrap();
+        assert!(regex.is_match("a.b"));
+        assert!(!regex.is_match("axb"));
+    }
+
+    #[test]
+    fn regex_is_case_insensitive() {
+        let regex = Filter::compile(FilterKind::Regex, r"discord\.gg/\w+").unwrap();
+        assert!(regex.is_match("join DISCORD.GG/abc"));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Filter::compile(FilterKind::Regex, "(unclosed").is_err());
+    }
+
+    #[test]
+    fn regex_size_limit() {
+        assert!(Filter::compile(FilterKind::Regex, r"[a-z]{100}").is_ok());
+        assert!(Filter::compile(FilterKind::Regex, r"(?:\w{1000}){1000}").is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Link rules
+//!
+//! The `invites` rule removes invites to other guilds, the `links` rule
+//! removes links to domains that aren't allowed.
+
+use std::{collections::HashSet, sync::LazyLock};
+
+use poise::serenity_prelude::{Context, GuildId, Message};
+use regex::Regex;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::Level;
+use url::Url;
+
+use super::{AutomodRule, Rule, RuleSettings, Violation};
+use crate::{invite::InviteStore, Data};
+
+static INVITE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:discord\.gg|discord(?:app)?\.com/invite)\s*/\s*([a-z0-9-]+)").unwrap()
+});
+
+static URL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)https?://[^\s<>]+").unwrap());
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct InviteOptions {
+    /// Guilds that can be invited to
+    #[serde_as(as = "HashSet<DisplayFromStr>")]
+    #[serde(default)]
+    pub allow: HashSet<GuildId>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LinkOptions {
+    /// If this isn't empty, only links to these domains and their subdomains
+    /// are allowed
+    #[serde(default)]
+    pub allow: HashSet<String>,
+    /// Links to these domains and their subdomains are forbidden
+    #[serde(default)]
+    pub deny: HashSet<String>,
+}
+
+impl LinkOptions {
+    /// Whether links to `host` are allowed
+    fn allows(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        let matches = |domain: &String| {
+            let domain = domain.to_lowercase();
+            host == domain || host.ends_with(&format!(".{}", domain))
+        };
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
+}
+
+#[async_trait]
+impl AutomodRule for Rule<InviteOptions> {
+    fn name(&self) -> &'static str {
+        "invites"
+    }
+
+    fn settings(&self) -> &RuleSettings {
+        &self.settings
+    }
+
+    async fn check(&self, ctx: &Context, _: &Data, message: &Message) -> Option<Violation> {
+        let guild = message.guild_id?;
+        let codes = INVITE
+            .captures_iter(&message.content)
+            .map(|c| c[1].to_string())
+            .collect::<HashSet<_>>();
+        if codes.is_empty() {
+            return None;
+        }
+
+        // invites of this guild are already known
+        let codes = {
+            let reader = ctx.data.read().await;
+            let store = reader.get::<InviteStore>()?.read().await;
+            let known = store.get(&guild);
+            codes
+                .into_iter()
+                .filter(|code| !known.map(|k| k.contains_key(code)).unwrap_or(false))
+                .collect::<Vec<_>>()
+        };
+
+        for code in codes {
+            let target = match ctx.http.get_invite(&code, false, false, None).await {
+                Ok(invite) => invite.guild.map(|g| g.id),
+                Err(e) => {
+                    event!(Level::DEBUG, invite = code, error = ?e, "failed to resolve invite {}: {}", code, e);
+                    None
+                }
+            };
+            match target {
+                Some(target) if target == guild || self.options.allow.contains(&target) => (),
+                _ => return Some(Violation::new("posting an invite to another server")),
+            }
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl AutomodRule for Rule<LinkOptions> {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn settings(&self) -> &RuleSettings {
+        &self.settings
+    }
+
+    async fn check(&self, _: &Context, _: &Data, message: &Message) -> Option<Violation> {
+        URL.find_iter(&message.content)
+            .filter_map(|url| Url::parse(url.as_str()).ok())
+            .filter_map(|url| url.host_str().map(str::to_string))
+            .find(|host| !self.options.allows(host))
+            .map(|host| Violation::new(format!("posting a link to `{}`", host)))
+    }
+}
@@ -0,0 +1,419 @@
+//! Spam rule
+//!
+//! Catches members who send too many messages in a short time, repeat the same
+//! message or mention too many users or roles at once. The recent messages of
+//! every member are kept in memory by the [`SpamTracker`].
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use poise::serenity_prelude::{Context, GuildId, Message, UserId};
+use serde::Deserialize;
+
+use super::{AutomodAction, AutomodRule, Rule, RuleSettings, Violation};
+use crate::Data;
+
+/// How many members are tracked at most
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SpamOptions {
+    /// How many messages can be sent within `interval`. `0` disables the
+    /// check.
+    #[serde(default = "default_messages")]
+    pub messages: usize,
+    /// In seconds
+    #[serde(default = "default_interval")]
+    pub interval: i64,
+    /// How often the same message can be sent within `duplicateinterval`.
+    /// `0` disables the check.
+    #[serde(default = "default_duplicates")]
+    pub duplicates: usize,
+    /// In seconds
+    #[serde(default = "default_duplicate_interval", rename = "duplicateinterval")]
+    pub duplicate_interval: i64,
+    /// How many users and roles a single message can mention. `0` disables
+    /// the check.
+    #[serde(default = "default_mentions")]
+    pub mentions: usize,
+}
+
+impl Default for SpamOptions {
+    fn default() -> Self {
+        Self {
+            messages: default_messages(),
+            interval: default_interval(),
+            duplicates: default_duplicates(),
+            duplicate_interval: default_duplicate_interval(),
+            mentions: default_mentions(),
+        }
+    }
+}
+
+fn default_messages() -> usize {
+    5
+}
+
+fn default_interval() -> i64 {
+    5
+}
+
+fn default_duplicates() -> usize {
+    3
+}
+
+fn default_duplicate_interval() -> i64 {
+    30
+}
+
+fn default_mentions() -> usize {
+    5
+}
+
+impl SpamOptions {
+    /// How long messages have to be remembered for these options
+    fn retention(&self) -> Duration {
+        Duration::seconds(self.interval.max(self.duplicate_interval))
+    }
+}
+
+/// Why a member is considered to be spamming
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spam {
+    /// Too many messages within the interval
+    Flood,
+    /// The same message too often within the interval
+    Duplicate,
+}
+
+impl Spam {
+    pub const fn reason(&self) -> &'static str {
+        match self {
+            Spam::Flood => "sending messages too fast",
+            Spam::Duplicate => "sending the same message repeatedly",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Seen {
+    at: DateTime<Utc>,
+    /// Hash of the normalized content, [`None`] for messages without text
+    content: Option<u64>,
+}
+
+/// Sliding windows of the recent messages of members, by guild and member
+#[derive(Debug)]
+pub struct SpamTracker {
+    windows: HashMap<(GuildId, UserId), VecDeque<Seen>>,
+    capacity: usize,
+}
+
+impl Default for SpamTracker {
+    fn default() -> Self {
+        Self::with_capacity(MAX_TRACKED)
+    }
+}
+
+impl SpamTracker {
+    /// A tracker that remembers at most `capacity` members
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            windows: HashMap::new(),
+            capacity,
+        }
+    }
+
+    /// How many members are tracked
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    /// Record a message sent at `at` and check whether its author is spamming
+    ///
+    /// The window of the member is reset after a violation, so a single burst
+    /// of messages is only punished once.
+    pub fn observe(
+        &mut self,
+        guild: GuildId,
+        user: UserId,
+        at: DateTime<Utc>,
+        content: &str,
+        options: &SpamOptions,
+    ) -> Option<Spam> {
+        let key = (guild, user);
+        if !self.windows.contains_key(&key) && self.windows.len() >= self.capacity {
+            self.evict(at - options.retention());
+        }
+
+        let window = self.windows.entry(key).or_default();
+        let since = at - options.retention();
+        while window.front().map(|s| s.at <= since).unwrap_or(false) {
+            window.pop_front();
+        }
+        let content = normalize(content);
+        window.push_back(Seen { at, content });
+
+        let flood = options.messages > 0
+            && window
+                .iter()
+                .filter(|s| s.at > at - Duration::seconds(options.interval))
+                .count()
+                > options.messages;
+        let duplicate = options.duplicates > 0
+            && content.is_some()
+            && window
+                .iter()
+                .filter(|s| {
+                    s.content == content
+                        && s.at > at - Duration::seconds(options.duplicate_interval)
+                })
+                .count()
+                > options.duplicates;
+
+        // nothing older than the retention is ever looked at, but a burst of
+        // messages with the same timestamp could still grow the window
+        let limit = options.messages.max(options.duplicates) + 1;
+        while window.len() > limit {
+            window.pop_front();
+        }
+
+        let spam = match (flood, duplicate) {
+            (_, true) => Some(Spam::Duplicate),
+            (true, false) => Some(Spam::Flood),
+            (false, false) => None,
+        };
+        if spam.is_some() {
+            self.windows.remove(&key);
+        }
+        spam
+    }
+
+    /// Make room for a new member by forgetting members who haven't sent a
+    /// message since `since`, or the least recently active one
+    fn evict(&mut self, since: DateTime<Utc>) {
+        self.windows
+            .retain(|_, w| w.back().map(|s| s.at > since).unwrap_or(false));
+        if self.windows.len() < self.capacity {
+            return;
+        }
+        if let Some(key) = self
+            .windows
+            .iter()
+            .min_by_key(|(_, w)| w.back().map(|s| s.at))
+            .map(|(key, _)| *key)
+        {
+            self.windows.remove(&key);
+        }
+    }
+}
+
+/// Hash of the content with case and whitespace ignored
+fn normalize(content: &str) -> Option<u64> {
+    let words = content
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return None;
+    }
+    let mut hasher = DefaultHasher::new();
+    words.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// The number of users and roles `message` mentions, `@everyone` counts as
+/// one
+fn mentions(message: &Message) -> usize {
+    message.mentions.iter().filter(|u| !u.bot).count()
+        + message.mention_roles.len()
+        + usize::from(message.mention_everyone)
+}
+
+#[async_trait]
+impl AutomodRule for Rule<SpamOptions> {
+    fn name(&self) -> &'static str {
+        "spam"
+    }
+
+    fn settings(&self) -> &RuleSettings {
+        &self.settings
+    }
+
+    fn default_action(&self) -> AutomodAction {
+        AutomodAction::Timeout
+    }
+
+    async fn check(&self, _: &Context, data: &Data, message: &Message) -> Option<Violation> {
+        let options = &self.options;
+        if options.mentions > 0 && mentions(message) > options.mentions {
+            return Some(Violation::new("mentioning too many users"));
+        }
+        let spam = data.spam.lock().unwrap().observe(
+            message.guild_id?,
+            message.author.id,
+            *message.timestamp,
+            &message.content,
+            options,
+        );
+        spam.map(|spam| Violation::new(spam.reason()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const GUILD: GuildId = GuildId(1);
+    const USER: UserId = UserId(2);
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn options() -> SpamOptions {
+        SpamOptions {
+            messages: 3,
+            interval: 5,
+            duplicates: 2,
+            duplicate_interval: 30,
+            mentions: 5,
+        }
+    }
+
+    #[test]
+    fn flood_within_interval() {
+        let mut tracker = SpamTracker::default();
+        let options = options();
+        for (i, second) in [0, 1, 2].into_iter().enumerate() {
+            let content = format!("message {}", i);
+            assert_eq!(
+                tracker.observe(GUILD, USER, at(second), &content, &options),
+                None
+            );
+        }
+        assert_eq!(
+            tracker.observe(GUILD, USER, at(3), "message 3", &options),
+            Some(Spam::Flood)
+        );
+    }
+
+    #[test]
+    fn messages_outside_interval_are_forgotten() {
+        let mut tracker = SpamTracker::default();
+        let options = options();
+        for (i, second) in [0, 3, 6, 9, 12, 15].into_iter().enumerate() {
+            let content = format!("message {}", i);
+            assert_eq!(
+                tracker.observe(GUILD, USER, at(second), &content, &options),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn duplicates_within_interval() {
+        let mut tracker = SpamTracker::default();
+        let options = options();
+        assert_eq!(
+            tracker.observe(GUILD, USER, at(0), "buy nitro", &options),
+            None
+        );
+        assert_eq!(
+            tracker.observe(GUILD, USER, at(10), "Buy  NITRO", &options),
+            None
+        );
+        assert_eq!(
+            tracker.observe(GUILD, USER, at(20), "buy nitro ", &options),
+            Some(Spam::Duplicate)
+        );
+        // the window is reset after a violation
+        assert_eq!(
+            tracker.observe(GUILD, USER, at(21), "buy nitro", &options),
+            None
+        );
+    }
+
+    #[test]
+    fn duplicates_outside_interval_are_allowed() {
+        let mut tracker = SpamTracker::default();
+        let options = options();
+        for second in [0, 20, 40, 60] {
+            assert_eq!(
+                tracker.observe(GUILD, USER, at(second), "hello", &options),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn messages_without_text_are_not_duplicates() {
+        let mut tracker = SpamTracker::default();
+        let options = options();
+        for second in [0, 10, 20] {
+            assert_eq!(tracker.observe(GUILD, USER, at(second), "", &options), None);
+        }
+    }
+
+    #[test]
+    fn users_and_guilds_are_tracked_separately() {
+        let mut tracker = SpamTracker::default();
+        let options = options();
+        for (guild, user) in [(GUILD, USER), (GUILD, UserId(3)), (GuildId(4), USER)] {
+            for second in 0..3 {
+                assert_eq!(
+                    tracker.observe(guild, user, at(second), &second.to_string(), &options),
+                    None
+                );
+            }
+        }
+        assert_eq!(tracker.len(), 3);
+    }
+
+    #[test]
+    fn disabled_checks() {
+        let mut tracker = SpamTracker::default();
+        let options = SpamOptions {
+            messages: 0,
+            duplicates: 0,
+            ..options()
+        };
+        for _ in 0..10 {
+            assert_eq!(tracker.observe(GUILD, USER, at(0), "spam", &options), None);
+        }
+    }
+
+    #[test]
+    fn tracker_is_bounded() {
+        let mut tracker = SpamTracker::with_capacity(2);
+        let options = options();
+        tracker.observe(GUILD, UserId(10), at(0), "a", &options);
+        tracker.observe(GUILD, UserId(11), at(1), "b", &options);
+        tracker.observe(GUILD, UserId(12), at(2), "c", &options);
+        assert_eq!(tracker.len(), 2);
+        // the least recently active member was forgotten
+        assert!(!tracker.windows.contains_key(&(GUILD, UserId(10))));
+
+        // members whose messages are outside the retention are forgotten first
+        tracker.observe(GUILD, UserId(13), at(100), "d", &options);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn window_is_bounded() {
+        let mut tracker = SpamTracker::default();
+        let options = SpamOptions {
+            messages: 0,
+            ..options()
+        };
+        for i in 0..100 {
+            tracker.observe(GUILD, USER, at(0), &i.to_string(), &options);
+        }
+        assert!(tracker.windows[&(GUILD, USER)].len() <= 3);
+    }
+}
@@ -0,0 +1,56 @@
+//! Sticker rule
+//!
+//! Discord's default stickers (the animated Lottie ones) are forbidden. By
+//! default, they are deleted and their sender is timed out for a minute.
+
+use std::collections::HashSet;
+
+use poise::serenity_prelude::{Context, Message, StickerFormatType, StickerId};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+
+use super::{AutomodAction, AutomodRule, Rule, RuleSettings, Violation};
+use crate::Data;
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct StickerOptions {
+    /// Stickers that are allowed even though they are default stickers
+    #[serde_as(as = "HashSet<DisplayFromStr>")]
+    #[serde(default)]
+    pub allow: HashSet<StickerId>,
+    /// Stickers that are forbidden even though they aren't default stickers
+    #[serde_as(as = "HashSet<DisplayFromStr>")]
+    #[serde(default)]
+    pub deny: HashSet<StickerId>,
+}
+
+#[async_trait]
+impl AutomodRule for Rule<StickerOptions> {
+    fn name(&self) -> &'static str {
+        "sticker"
+    }
+
+    fn settings(&self) -> &RuleSettings {
+        &self.settings
+    }
+
+    fn default_action(&self) -> AutomodAction {
+        AutomodAction::Timeout
+    }
+
+    async fn check(&self, _: &Context, _: &Data, message: &Message) -> Option<Violation> {
+        let options = &self.options;
+        let sticker = message.sticker_items.iter().find(|sticker| {
+            options.deny.contains(&sticker.id)
+                || (sticker.format_type == StickerFormatType::Lottie
+                    && !options.allow.contains(&sticker.id))
+        })?;
+        Some(Violation::new(match sticker.format_type {
+            StickerFormatType::Lottie if !options.deny.contains(&sticker.id) => {
+                "sending a default sticker"
+            }
+            _ => "sending a forbidden sticker",
+        }))
+    }
+}
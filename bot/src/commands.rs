@@ -1,7 +1,28 @@
+mod admin;
+mod config;
+mod filter;
 mod invite;
 mod moderation;
+mod owner;
+mod quarantine;
+mod raidmode;
+mod template;
 
+#[doc(inline)]
+pub use admin::admin;
+#[doc(inline)]
+pub use config::config;
+#[doc(inline)]
+pub use filter::filter;
 #[doc(inline)]
 pub use invite::invite;
 #[doc(inline)]
 pub use moderation::hackban;
+#[doc(inline)]
+pub use owner::{reload_config, shards};
+#[doc(inline)]
+pub use quarantine::quarantine;
+#[doc(inline)]
+pub use raidmode::raidmode;
+#[doc(inline)]
+pub use template::template;
@@ -0,0 +1,286 @@
+use chrono::Utc;
+use comfy_table::{presets::NOTHING, Table};
+use poise::serenity_prelude::{CacheHttp, GuildId, ShardId};
+
+use super::owner::is_owner;
+use crate::{invite::InviteStore, util::format_duration, Context, Result};
+
+mod sql;
+
+use sql::sql;
+
+/// Discord doesn't allow longer messages
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// Administrate the bot
+#[command(
+    slash_command,
+    prefix_command,
+    check = "is_owner",
+    hide_in_help,
+    subcommands(
+        "status",
+        "guilds",
+        "leave",
+        "restart_shard",
+        "resync_invites",
+        "maintenance",
+        "deferred",
+        "sql"
+    )
+)]
+pub async fn admin(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Show how the bot is doing
+#[command(slash_command, prefix_command, check = "is_owner", ephemeral)]
+pub async fn status(ctx: Context<'_>) -> Result<()> {
+    let data = ctx.data();
+    let cache = &ctx.discord().cache;
+    let invites = match ctx.discord().data.read().await.get::<InviteStore>() {
+        Some(store) => store.read().await.values().map(|i| i.len()).sum(),
+        None => 0,
+    };
+    let shards = ctx
+        .framework()
+        .shard_manager
+        .lock()
+        .await
+        .runners
+        .lock()
+        .await
+        .len();
+
+    let mut table = Table::new();
+    table.load_preset(NOTHING);
+    table.add_row(["Uptime", &format_duration(Utc::now() - data.started)]);
+    table.add_row(["Shards", &format!("{} of {}", shards, cache.shard_count())]);
+    table.add_row(["Guilds", &cache.guild_count().to_string()]);
+    table.add_row(["Cached invites", &invites.to_string()]);
+    table.add_row([
+        "DB connections",
+        &format!("{} ({} idle)", data.pool.size(), data.pool.num_idle()),
+    ]);
+    table.add_row([
+        "Maintenance",
+        match data.maintenance.is_enabled() || data.config.get().maintenance {
+            true => "on",
+            false => "off",
+        },
+    ]);
+    ctx.say(format!("```\n{}\n```", table)).await?;
+    Ok(())
+}
+
+/// List the guilds the bot is on, with the most members first
+#[command(slash_command, prefix_command, check = "is_owner", ephemeral)]
+pub async fn guilds(ctx: Context<'_>) -> Result<()> {
+    let cache = &ctx.discord().cache;
+    let mut guilds = cache
+        .guilds()
+        .into_iter()
+        .map(|id| {
+            let (name, members) = cache
+                .guild_field(id, |g| (g.name.clone(), g.member_count))
+                .unwrap_or_else(|| ("(unavailable)".to_string(), 0));
+            (id, name, members)
+        })
+        .collect::<Vec<_>>();
+    guilds.sort_by_key(|g| std::cmp::Reverse(g.2));
+
+    // leave some room for the code block and the note below
+    let limit = MAX_MESSAGE_LENGTH - 100;
+    let mut shown = guilds.len();
+    let mut table = guild_table(&guilds);
+    while table.len() > limit {
+        shown = (shown * limit / table.len()).min(shown - 1);
+        table = guild_table(&guilds[..shown]);
+    }
+    let mut content = format!("```\n{}\n```", table);
+    if shown < guilds.len() {
+        content.push_str(&format!("…and {} more.", guilds.len() - shown));
+    }
+    ctx.say(content).await?;
+    Ok(())
+}
+
+/// Make the bot leave a guild
+#[command(slash_command, prefix_command, check = "is_owner", ephemeral)]
+pub async fn leave(
+    ctx: Context<'_>,
+    #[description = "The ID of the guild"] guild: String,
+) -> Result<()> {
+    let guild = parse_guild(&guild)?;
+    let name = ctx
+        .discord()
+        .cache
+        .guild_field(guild, |g| g.name.clone())
+        .ok_or_else(|| anyhow!("I'm not on guild {}.", guild))?;
+    guild.leave(ctx.discord().http()).await?;
+    ctx.say(format!("Left {} ({}).", name, guild)).await?;
+    Ok(())
+}
+
+/// Reconnect a shard of this process
+#[command(
+    slash_command,
+    prefix_command,
+    rename = "restart-shard",
+    check = "is_owner",
+    ephemeral
+)]
+pub async fn restart_shard(
+    ctx: Context<'_>,
+    #[description = "The ID of the shard"] shard: u64,
+) -> Result<()> {
+    let mut shard_manager = ctx.framework().shard_manager.lock().await;
+    if !shard_manager.has(ShardId(shard)).await {
+        return Err(anyhow!("Shard {} doesn't run in this process.", shard).into());
+    }
+    shard_manager.restart(ShardId(shard)).await;
+    ctx.say(format!("Restarting shard {}.", shard)).await?;
+    Ok(())
+}
+
+/// Load the invites of a guild again, e.g. if they were missed while
+/// disconnected
+#[command(
+    slash_command,
+    prefix_command,
+    rename = "resync-invites",
+    check = "is_owner",
+    ephemeral
+)]
+pub async fn resync_invites(
+    ctx: Context<'_>,
+    #[description = "The ID of the guild, this guild if not set"] guild: Option<String>,
+) -> Result<()> {
+    let guild = match guild {
+        Some(guild) => parse_guild(&guild)?,
+        None => ctx
+            .guild_id()
+            .ok_or_else(|| anyhow!("Run this in a guild or give the ID of one."))?,
+    };
+    let invites = InviteStore::load(ctx.discord(), guild)
+        .await
+        .map_err(|e| anyhow!("Cannot load the invites of guild {}: {}", guild, e))?;
+    ctx.say(format!("Loaded {} invite(s) for guild {}.", invites, guild))
+        .await?;
+    Ok(())
+}
+
+/// Turn maintenance mode on or off, in all guilds or in one
+///
+/// Maintenance in a single guild is stored with the guild's settings. For all
+/// guilds it's only kept until the bot restarts, set `maintenance` in the
+/// config file to keep it on across restarts.
+#[command(slash_command, prefix_command, check = "is_owner", ephemeral)]
+pub async fn maintenance(
+    ctx: Context<'_>,
+    #[description = "Whether maintenance mode is on"] enabled: bool,
+    #[description = "The ID of the guild, all guilds if not set"] guild: Option<String>,
+) -> Result<()> {
+    let data = ctx.data();
+    let state = match enabled {
+        true => "on",
+        false => "off",
+    };
+    match guild {
+        Some(guild) => {
+            let guild = parse_guild(&guild)?;
+            data.settings
+                .set_maintenance(&data.pool, guild, enabled, ctx.author().id)
+                .await?;
+            ctx.say(format!("Maintenance mode is {} in guild {}.", state, guild))
+                .await?;
+        }
+        None => {
+            data.maintenance.set(enabled);
+            let mut reply = format!("Maintenance mode is {}.", state);
+            if enabled {
+                reply.push_str(" It's turned off again when the bot restarts.");
+            } else if data.config.get().maintenance {
+                reply.push_str(" It stays on until `maintenance` is removed from the config file.");
+            }
+            ctx.say(reply).await?;
+        }
+    }
+    Ok(())
+}
+
+/// List the actions that were held back during maintenance
+#[command(slash_command, prefix_command, check = "is_owner", ephemeral)]
+pub async fn deferred(
+    ctx: Context<'_>,
+    #[description = "The ID of the guild, this guild if not set"] guild: Option<String>,
+) -> Result<()> {
+    let guild = match guild {
+        Some(guild) => parse_guild(&guild)?,
+        None => ctx
+            .guild_id()
+            .ok_or_else(|| anyhow!("Run this in a guild or give the ID of one."))?,
+    };
+    let deferred = sqlx::query!(
+        r#"
+    SELECT "user", action, reason, created_at FROM deferred_actions
+    WHERE guild = $1
+    ORDER BY created_at DESC
+    LIMIT 20
+    "#,
+        guild.0.to_string(),
+    )
+    .fetch_all(&ctx.data().pool)
+    .await?;
+    if deferred.is_empty() {
+        ctx.say(format!("No actions were held back in guild {}.", guild))
+            .await?;
+        return Ok(());
+    }
+
+    let mut lines = Vec::new();
+    for row in deferred {
+        let line = format!(
+            "<t:{}:f> **{}** <@{}>: {}",
+            row.created_at.timestamp(),
+            row.action,
+            row.user,
+            row.reason
+        );
+        if lines.iter().map(|l: &String| l.len() + 1).sum::<usize>() + line.len()
+            > MAX_MESSAGE_LENGTH - 100
+        {
+            break;
+        }
+        lines.push(line);
+    }
+    ctx.send(|m| {
+        m.content(format!(
+            "Latest actions held back in guild {}:\n{}",
+            guild,
+            lines.join("\n")
+        ))
+        .allowed_mentions(|a| a.empty_parse())
+    })
+    .await?;
+    Ok(())
+}
+
+fn guild_table(guilds: &[(GuildId, String, u64)]) -> String {
+    let mut table = Table::new();
+    table.load_preset(NOTHING);
+    table.set_header(["Guild", "Name", "Members"]);
+    for (id, name, members) in guilds {
+        table.add_row([id.0.to_string(), name.clone(), members.to_string()]);
+    }
+    table.to_string()
+}
+
+fn parse_guild(guild: &str) -> Result<GuildId> {
+    Ok(GuildId(
+        guild
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("`{}` is not a guild ID.", guild))?,
+    ))
+}
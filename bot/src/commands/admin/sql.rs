@@ -0,0 +1,111 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use comfy_table::{presets::NOTHING, Table};
+use futures::{StreamExt, TryStreamExt};
+use sqlx::{postgres::PgRow, types::Uuid, Column, Decode, Postgres, Row, Type, TypeInfo, ValueRef};
+
+use super::{super::owner::is_owner, MAX_MESSAGE_LENGTH};
+use crate::{Context, Result};
+
+/// How many rows are shown at most
+const MAX_ROWS: usize = 25;
+
+/// Run a read-only SQL query against the database of the bot
+///
+/// The query runs in a read-only transaction with a statement timeout, and
+/// as a prepared statement, so it can't smuggle in more statements. Read-only
+/// only keeps it from writing to tables: functions with side effects, like
+/// `pg_terminate_backend` or `pg_advisory_lock`, still run.
+#[command(slash_command, prefix_command, check = "is_owner", ephemeral)]
+pub async fn sql(
+    ctx: Context<'_>,
+    #[description = "The query"]
+    #[rest]
+    query: String,
+) -> Result<()> {
+    let query = query
+        .trim()
+        .trim_start_matches("```sql")
+        .trim_matches('`')
+        .trim();
+
+    let mut tx = ctx.data().pool.begin().await?;
+    sqlx::query("SET TRANSACTION READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("SET LOCAL statement_timeout = '5s'")
+        .execute(&mut *tx)
+        .await?;
+    // only the rows that are shown are fetched, plus one to tell whether
+    // there are more
+    let rows: Vec<PgRow> = sqlx::query(query)
+        .fetch(&mut *tx)
+        .take(MAX_ROWS + 1)
+        .try_collect()
+        .await
+        .map_err(|e| anyhow!("The query failed: {}", e))?;
+    tx.rollback().await?;
+
+    let first = match rows.first() {
+        Some(first) => first,
+        None => {
+            ctx.say("No rows.").await?;
+            return Ok(());
+        }
+    };
+    let mut table = Table::new();
+    table.load_preset(NOTHING);
+    table.set_header(first.columns().iter().map(|c| c.name()));
+    for row in rows.iter().take(MAX_ROWS) {
+        table.add_row((0..row.len()).map(|i| format_value(row, i)));
+    }
+
+    let mut content = format!("```\n{}\n```", table);
+    if content.len() > MAX_MESSAGE_LENGTH - 50 {
+        let mut end = MAX_MESSAGE_LENGTH - 54;
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        content.truncate(end);
+        content.push_str("\n…\n```");
+    }
+    if rows.len() > MAX_ROWS {
+        content.push_str(&format!("Only the first {} rows are shown.", MAX_ROWS));
+    }
+    ctx.say(content).await?;
+    Ok(())
+}
+
+/// Render the column `i` of `row` for the types the bot uses
+fn format_value(row: &PgRow, i: usize) -> String {
+    match row.try_get_raw(i) {
+        Ok(value) if value.is_null() => return "NULL".to_string(),
+        Ok(_) => (),
+        Err(e) => return format!("<{}>", e),
+    }
+    let ty = row.columns()[i].type_info().name().to_string();
+    let value = match ty.as_str() {
+        "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" => decode::<String>(row, i),
+        "TEXT[]" | "VARCHAR[]" => row
+            .try_get::<Vec<String>, _>(i)
+            .ok()
+            .map(|v| format!("{:?}", v)),
+        "BOOL" => decode::<bool>(row, i),
+        "INT2" => decode::<i16>(row, i),
+        "INT4" => decode::<i32>(row, i),
+        "INT8" => decode::<i64>(row, i),
+        "FLOAT4" => decode::<f32>(row, i),
+        "FLOAT8" => decode::<f64>(row, i),
+        "TIMESTAMPTZ" => decode::<DateTime<Utc>>(row, i),
+        "TIMESTAMP" => decode::<NaiveDateTime>(row, i),
+        "UUID" => decode::<Uuid>(row, i),
+        _ => None,
+    };
+    value.unwrap_or_else(|| format!("<{}>", ty))
+}
+
+fn decode<'r, T>(row: &'r PgRow, i: usize) -> Option<String>
+where
+    T: Decode<'r, Postgres> + Type<Postgres> + ToString,
+{
+    row.try_get::<T, _>(i).ok().map(|v| v.to_string())
+}
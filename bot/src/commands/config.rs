@@ -0,0 +1,120 @@
+use poise::serenity_prelude::Color;
+
+use crate::{settings::Setting, Context, Result};
+
+/// View and change the settings of this server
+#[command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("get", "set", "reset")
+)]
+pub async fn config(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Show the current settings
+#[command(slash_command, ephemeral)]
+pub async fn get(
+    ctx: Context<'_>,
+    #[description = "Only show this setting"] setting: Option<Setting>,
+) -> Result<()> {
+    let guild = ctx.guild_id().unwrap();
+    let config = ctx.data().guild(guild);
+    let changed = ctx.data().settings.get(guild);
+
+    let lines = setting
+        .map(|s| vec![s])
+        .unwrap_or_else(|| Setting::ALL.to_vec())
+        .into_iter()
+        .map(|s| {
+            format!(
+                "**{}**: {}{}",
+                s,
+                s.current(&config),
+                match changed.get(s) {
+                    Some(_) => "",
+                    None => " *(config file)*",
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+    ctx.send(|reply| {
+        reply.embed(|e| {
+            e.title("Settings");
+            e.color(Color::BLURPLE);
+            e.description(lines.join("\n"));
+            e.footer(|f| {
+                f.text("Settings that weren't changed with /config come from the config file")
+            })
+        });
+        reply.ephemeral(true)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Change a setting
+#[command(slash_command, ephemeral)]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "The setting you want to change"] setting: Setting,
+    #[description = "A channel, a role, `on`/`off` or `kick`/`quarantine`, depending on the \
+                     setting"]
+    value: String,
+) -> Result<()> {
+    let guild = ctx.guild().unwrap();
+    let mut settings = ctx.data().settings.get(guild.id);
+    settings
+        .set(setting, &value)
+        .map_err(|e| anyhow!("`{}` has to be {}: {}", setting, setting.kind(), e))?;
+    settings.validate(setting, &guild)?;
+
+    ctx.data()
+        .settings
+        .set(
+            &ctx.data().pool,
+            guild.id,
+            setting,
+            // set above
+            &settings.get(setting).unwrap(),
+            ctx.author().id,
+        )
+        .await?;
+    ctx.say(format!(
+        "`{}` is now {}.",
+        setting,
+        setting.current(&ctx.data().guild(guild.id))
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Reset a setting to the value of the config file
+#[command(slash_command, ephemeral)]
+pub async fn reset(
+    ctx: Context<'_>,
+    #[description = "The setting you want to reset"] setting: Setting,
+) -> Result<()> {
+    let guild = ctx.guild_id().unwrap();
+    let changed = ctx
+        .data()
+        .settings
+        .reset(&ctx.data().pool, guild, setting)
+        .await?;
+    match changed {
+        true => {
+            ctx.say(format!(
+                "`{}` is reset to {}.",
+                setting,
+                setting.current(&ctx.data().guild(guild))
+            ))
+            .await?
+        }
+        false => {
+            ctx.say(format!("`{}` wasn't changed with /config.", setting))
+                .await?
+        }
+    };
+    Ok(())
+}
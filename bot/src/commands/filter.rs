@@ -0,0 +1,161 @@
+use poise::serenity_prelude::{Color, GuildChannel};
+
+use crate::{
+    automod::{normalize, AutomodAction, Filter, FilterKind},
+    Context, Result,
+};
+
+/// Manage the content filter
+#[command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("add", "remove", "list", "test")
+)]
+pub async fn filter(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Add a filter
+#[command(slash_command, ephemeral)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Whether the pattern is a regular expression or a comma separated word list"]
+    kind: FilterKind,
+    #[description = "The regular expression or the words"] pattern: String,
+    #[description = "What happens if a message matches"] action: AutomodAction,
+    #[description = "Shown to the member and in the mod log"] reason: String,
+    #[description = "Only filter messages in this channel or category"]
+    #[channel_types("Text", "News", "Category")]
+    scope: Option<GuildChannel>,
+) -> Result<()> {
+    let guild = ctx.guild_id().unwrap();
+    Filter::compile(kind, &pattern).map_err(|e| anyhow!("Invalid pattern: {}", e))?;
+
+    let id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO filters (guild, kind, pattern, action, reason, scope, created_by)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    RETURNING id
+    "#,
+        guild.0.to_string(),
+        kind.name(),
+        pattern,
+        action.name(),
+        reason,
+        scope.as_ref().map(|s| s.id.0.to_string()),
+        ctx.author().id.0.to_string(),
+    )
+    .fetch_one(&ctx.data().pool)
+    .await?;
+    ctx.data().filters.invalidate(guild);
+
+    ctx.say(format!("Added filter #{}.", id)).await?;
+    Ok(())
+}
+
+/// Remove a filter
+#[command(slash_command, ephemeral)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The number of the filter"] id: i64,
+) -> Result<()> {
+    let guild = ctx.guild_id().unwrap();
+    let deleted = sqlx::query!(
+        r#"DELETE FROM filters WHERE guild = $1 AND id = $2"#,
+        guild.0.to_string(),
+        id,
+    )
+    .execute(&ctx.data().pool)
+    .await?
+    .rows_affected();
+    ctx.data().filters.invalidate(guild);
+
+    match deleted {
+        0 => ctx.say(format!("There is no filter #{}.", id)).await?,
+        _ => ctx.say(format!("Removed filter #{}.", id)).await?,
+    };
+    Ok(())
+}
+
+/// List all filters
+#[command(slash_command, ephemeral)]
+pub async fn list(ctx: Context<'_>) -> Result<()> {
+    let guild = ctx.guild_id().unwrap();
+    let filters = ctx.data().filters.get(&ctx.data().pool, guild).await?;
+    if filters.is_empty() {
+        ctx.say("There are no filters.").await?;
+        return Ok(());
+    }
+
+    let lines = filters
+        .iter()
+        .map(|f| {
+            format!(
+                "**#{}** {} `{}` → {}{}: {}",
+                f.id,
+                f.kind,
+                f.pattern.replace('`', "ˋ"),
+                f.action,
+                f.scope.map(|s| format!(" in <#{}>", s)).unwrap_or_default(),
+                f.reason
+            )
+        })
+        .collect::<Vec<_>>();
+    ctx.send(|reply| {
+        // embed descriptions are limited to 4096 characters
+        let mut description = String::new();
+        for line in &lines {
+            if description.len() + line.len() + 1 > 4000 {
+                description.push('…');
+                break;
+            }
+            description.push_str(line);
+            description.push('\n');
+        }
+        reply.embed(|e| {
+            e.title("Filters");
+            e.color(Color::BLURPLE);
+            e.description(description)
+        });
+        reply.ephemeral(true)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Check which filters a message would match
+#[command(slash_command, ephemeral)]
+pub async fn test(
+    ctx: Context<'_>,
+    #[description = "The text of the message"] text: String,
+) -> Result<()> {
+    let guild = ctx.guild_id().unwrap();
+    let filters = ctx.data().filters.get(&ctx.data().pool, guild).await?;
+    let normalized = normalize(&text);
+    let matches = filters
+        .iter()
+        .filter(|f| f.matches(&text, &normalized))
+        .map(|f| format!("#{} ({}): {}", f.id, f.action, f.reason))
+        .collect::<Vec<_>>();
+
+    let normalized = normalized.replace('`', "ˋ");
+    match matches.is_empty() {
+        true => {
+            ctx.say(format!(
+                "No filter matches. Normalized text: `{}`",
+                normalized
+            ))
+            .await?
+        }
+        false => {
+            ctx.say(format!(
+                "Matching filters (regardless of their channel):\n{}\nNormalized text: `{}`",
+                matches.join("\n"),
+                normalized
+            ))
+            .await?
+        }
+    };
+    Ok(())
+}
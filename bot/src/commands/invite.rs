@@ -1,9 +1,12 @@
-use chrono::{Duration, Utc};
+use std::collections::HashMap;
+
+use chrono::Utc;
 use comfy_table::{presets::NOTHING, Cells, Table};
 use poise::serenity_prelude::{CacheHttp, Member, UserId};
 
 use crate::{
     invite::{Invite, InviteStore},
+    util::format_duration,
     Context, Result,
 };
 
@@ -54,6 +57,23 @@ pub async fn list(
 
 /// Helper function to list the invites of an user
 pub async fn list_invites(ctx: Context<'_>, user: UserId, display_inviter: bool) -> Result<()> {
+    // members who haven't passed the verification yet don't count
+    let verified: HashMap<String, i64> = sqlx::query!(
+        r#"
+    SELECT invite, COUNT(*) FILTER (WHERE confirmed) AS "verified!"
+    FROM invited_members
+    WHERE guild = $1 AND inviter = $2
+    GROUP BY invite
+    "#,
+        ctx.guild_id().unwrap().0.to_string(),
+        user.0.to_string(),
+    )
+    .fetch_all(&ctx.data().pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.invite, row.verified))
+    .collect();
+
     let reader = ctx.discord().data.read().await;
     let reader = reader
         .get::<InviteStore>()
@@ -65,7 +85,7 @@ pub async fn list_invites(ctx: Context<'_>, user: UserId, display_inviter: bool)
         .ok_or_else(|| anyhow!("No invites stored for this guild."))?
         .iter()
         .filter(|(_, invite)| invite.inviter == user);
-    let table = generate_invite_table(invites, display_inviter, user);
+    let table = generate_invite_table(invites, &verified, display_inviter, user);
     ctx.send(|reply| {
         reply.content(table);
         reply.ephemeral(true)
@@ -76,6 +96,7 @@ pub async fn list_invites(ctx: Context<'_>, user: UserId, display_inviter: bool)
 
 fn generate_invite_table<'a>(
     invites: impl Iterator<Item = (&'a String, &'a Invite)>,
+    verified: &HashMap<String, i64>,
     display_inviter: bool,
     member: UserId,
 ) -> String {
@@ -88,7 +109,7 @@ fn generate_invite_table<'a>(
         };
     }
     let mut table = Table::new();
-    let mut headers = vec!["Invite", "Uses", "Expires"];
+    let mut headers = vec!["Invite", "Uses", "Verified", "Expires"];
     if display_inviter {
         headers.insert(0, "Inviter");
     }
@@ -105,52 +126,24 @@ fn generate_invite_table<'a>(
                     // \u{221E} = infinity symbol
                     .unwrap_or_else(|| "\u{221E}".to_string())
             );
+            let verified = verified.get(code).copied().unwrap_or(0).to_string();
             let expires = match meta.max_age {
-                Some(t) => {
-                    let mut expires_in = t - Utc::now();
-                    let days = match expires_in.num_days() {
-                        0 => None,
-                        x => {
-                            expires_in -= Duration::days(x);
-                            Some(format!("{}d", x))
-                        }
-                    };
-                    let hours = match expires_in.num_hours() {
-                        0 => None,
-                        x => {
-                            expires_in -= Duration::hours(x);
-                            Some(format!("{}h", x))
-                        }
-                    };
-                    let minutes = match expires_in.num_minutes() {
-                        0 => None,
-                        x => {
-                            expires_in -= Duration::minutes(x);
-                            Some(format!("{}min", x))
-                        }
-                    };
-                    let seconds = match expires_in.num_seconds() {
-                        0 => None,
-                        x => Some(format!("{}s", x)),
-                    };
-
-                    format!(
-                        "{} {} {} {}",
-                        days.unwrap_or_default(),
-                        hours.unwrap_or_default(),
-                        minutes.unwrap_or_default(),
-                        seconds.unwrap_or_default(),
-                    )
-                    .trim()
-                    .to_string()
-                }
+                Some(t) => format_duration(t - Utc::now()),
                 None => "\u{221E}".to_string(),
             };
             match display_inviter {
-                true => [meta.inviter.0.to_string(), code.to_string(), uses, expires]
+                true => [
+                    meta.inviter.0.to_string(),
+                    code.to_string(),
+                    uses,
+                    verified,
+                    expires,
+                ]
+                .into_iter()
+                .into(),
+                false => [code.to_string(), uses, verified, expires]
                     .into_iter()
                     .into(),
-                false => [code.to_string(), uses, expires].into_iter().into(),
             }
         })
         .for_each(|row| {
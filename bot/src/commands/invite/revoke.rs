@@ -10,7 +10,12 @@ use poise::{
 use sqlx::PgPool;
 use tracing::{Instrument, Level};
 
-use crate::{invite::InviteStore, Context, Result};
+use crate::{
+    invite::InviteStore,
+    modlog::{Action, Entry, ModLog},
+    quarantine::Remedy,
+    Context, Result,
+};
 
 /// Revoke a single or all invites created by a you or an other member
 #[instrument(skip(ctx))]
@@ -20,11 +25,20 @@ pub async fn revoke(
     #[description = "The code of the invite you want to revoke"]
     #[autocomplete = "autocomplete_invite"]
     invite: Option<String>,
-    #[description = "If set to true, kick all members you used this invite to join the server"]
-    kick: Option<bool>,
+    #[description = "Kick or quarantine all members who used this invite to join the server"]
+    remedy: Option<Remedy>,
     #[description = "Only required if you want to revoke all invites from this member"]
     member: Option<Member>,
 ) -> Result<()> {
+    // revoking stays possible during maintenance, only the remedy is held back
+    if remedy.is_some() && ctx.data().in_maintenance(ctx.guild_id().unwrap()) {
+        return Err(anyhow!(
+            "Maintenance mode is active, members can't be kicked or quarantined. Revoke without a \
+             remedy or try again later."
+        )
+        .into());
+    }
+
     let privileged = ctx
         .guild()
         .unwrap()
@@ -54,7 +68,7 @@ pub async fn revoke(
                 .iter()
                 .filter(|(_, meta)| meta.inviter == member.user.id)
             {
-                revoked.push(delete_invite(ctx, invite, kick.unwrap_or(false)).await?);
+                revoked.push(delete_invite(ctx, invite, remedy).await?);
             }
             match revoked.len() {
                 0 => ctx.say("No invites revoked.").await?,
@@ -64,7 +78,7 @@ pub async fn revoke(
         }
         None => match invite {
             Some(invite) => {
-                delete_invite(ctx, &invite, kick.unwrap_or(false))
+                delete_invite(ctx, &invite, remedy)
                     .await
                     .map_err(|_| anyhow!("Failed to delete invite {}", invite))?;
                 ctx.say(format!("Successfully revoked invite `{}`.", invite))
@@ -86,7 +100,7 @@ pub async fn revoke(
                     .iter()
                     .filter(|(_, meta)| meta.inviter == ctx.author().id)
                 {
-                    revoked.push(delete_invite(ctx, invite, kick.unwrap_or(false)).await?);
+                    revoked.push(delete_invite(ctx, invite, remedy).await?);
                 }
                 match revoked.len() {
                     0 => ctx.say("No invites revoked.").await?,
@@ -102,7 +116,7 @@ pub async fn revoke(
 }
 
 #[instrument(skip(ctx))]
-async fn delete_invite(ctx: Context<'_>, invite: &str, kick: bool) -> Result<String> {
+async fn delete_invite(ctx: Context<'_>, invite: &str, remedy: Option<Remedy>) -> Result<String> {
     let invite = SerenityInvite::get(ctx.discord().http(), invite, false, false, None)
         .await
         .map_err(|_| anyhow!("Invalid invite."))?;
@@ -111,31 +125,37 @@ async fn delete_invite(ctx: Context<'_>, invite: &str, kick: bool) -> Result<Str
         .delete(ctx.discord().http())
         .await
         .map_err(|e| anyhow!("Cannot delete invite: {}", e))?;
-    if kick {
+    let guild = ctx.guild_id().unwrap();
+    if let Some(ref inviter) = invite.inviter {
+        ModLog::log(
+            ctx.discord(),
+            ctx.data(),
+            Entry::new(guild, Action::InviteRevoke, inviter.id, ctx.author().id)
+                .reason(format!("Invite `{}` revoked", invite.code)),
+        )
+        .await;
+    }
+    if let Some(remedy) = remedy {
         sqlx::query!(
                 r#"SELECT "user", "used_at" FROM invited_members WHERE invite = $1"#,
                 &invite.code
             )
             .fetch(&ctx.data().pool)
             .try_for_each_concurrent(None, |row| async move {
+                let user = UserId(row.user.parse().unwrap());
+                let reason = format!("Invite revoked by {}#{} ({})", ctx.author().name, ctx.author().discriminator, ctx.author().id);
                 // todo: check for independence limit
-                match ctx
-                    .guild()
-                    .unwrap()
-                    .kick_with_reason(
-                        ctx.discord().http(),
-                        UserId(row.user.parse().unwrap()),
-                        &format!("Invite revoked by {}#{} ({})", ctx.author().name, ctx.author().discriminator, ctx.author().id),
-                    )
+                match remedy
+                    .apply(ctx.discord(), ctx.data(), guild, user, ctx.author().id, &reason)
                     .await
                 {
-                    Ok(_) => event!(Level::INFO, "Kicked member {}", row.user),
+                    Ok(_) => event!(Level::INFO, "Applied {} to member {}", remedy, row.user),
                     // FIXME: this will warn even for members that simply aren't in the guild anymore
-                    Err(e) => event!(Level::WARN, member = row.user, error = ?e, "Failed to kick member {}: {}", row.user, e),
+                    Err(e) => event!(Level::WARN, member = row.user, error = ?e, "Failed to {} member {}: {}", remedy, row.user, e),
                 }
                 Ok(())
             })
-            .instrument(info_span!("invite_revoke_kick_members"))
+            .instrument(info_span!("invite_revoke_remove_members"))
             .await?;
     }
     Ok(invite.code)
@@ -150,7 +170,7 @@ async fn db_invites<'a>(pool: &'a PgPool, inviter: &str) -> impl Stream<Item = S
     .fetch(pool)
     .map_ok(|r| r.invite)
     // just ignore rows that returned an error
-    .then(|r| future::ready(stream::iter(r.into_iter())))
+    .then(|r| future::ready(stream::iter(r)))
     .flatten()
 }
 #[instrument(skip(ctx))]
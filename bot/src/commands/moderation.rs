@@ -1,30 +1,43 @@
-use poise::serenity_prelude::{Color, UserId};
+use poise::serenity_prelude::{Color, Guild, Member, SerenityError, UserId};
 
-use crate::{Context, Result};
+use crate::{
+    maintenance::allows_moderation,
+    modlog::{Action, Entry},
+    util::ban_with_notification,
+    Context, Result,
+};
 
 /// Moderate stuff
 #[command(
     slash_command,
     guild_only,
     required_permissions = "BAN_MEMBERS",
-    required_bot_permissions = "BAN_MEMBERS"
+    required_bot_permissions = "BAN_MEMBERS",
+    check = "allows_moderation"
 )]
 pub async fn hackban(
     ctx: Context<'_>,
     #[description = "The member you want to ban"] user: UserId,
+    #[description = "Delete the messages of the last days (0-7)"]
+    #[min = 0]
+    #[max = 7]
+    delete_days: Option<u8>,
     reason: Option<String>,
 ) -> Result<()> {
+    let guild = ctx.guild().unwrap();
+    let delete_days = delete_days.unwrap_or(0);
+
+    validate_target(ctx, &guild, user).await?;
+
+    let mut entry = Entry::new(guild.id, Action::Ban, user, ctx.author().id);
     if let Some(ref reason) = reason {
-        ctx.guild()
-            .unwrap()
-            .ban_with_reason(&ctx.discord().http, user, 0, reason)
-            .await?;
-    } else {
-        ctx.guild()
-            .unwrap()
-            .ban(&ctx.discord().http, user, 0)
-            .await?;
+        entry = entry.reason(reason);
     }
+    // the user is told about the ban, with a button to appeal it, while they
+    // can still be reached
+    let target = user.to_user(ctx.discord()).await?;
+    let case =
+        ban_with_notification(ctx.discord(), ctx.data(), &target, entry, delete_days).await?;
     ctx.send(|b| {
         b.ephemeral(true);
         b.embed(|e| {
@@ -38,6 +51,12 @@ pub async fn hackban(
             } else {
                 e.description(format!("User `{}` got banned", user));
             }
+            if let Some(case) = case {
+                match case.link() {
+                    Some(link) => e.field("Case", format!("[#{}]({})", case.id, link), true),
+                    None => e.field("Case", format!("#{}", case.id), true),
+                };
+            }
             e
         });
         b
@@ -45,3 +64,73 @@ pub async fn hackban(
     .await?;
     Ok(())
 }
+
+/// Check that `target` may be sanctioned by the author of `ctx`
+///
+/// Neither the bot itself, the author nor the owner of the guild can be
+/// sanctioned. If `target` is a member of the guild, their highest role must
+/// also be below the highest role of the author and of the bot.
+pub async fn validate_target(ctx: Context<'_>, guild: &Guild, target: UserId) -> Result<()> {
+    let bot = ctx.framework().bot_id;
+    if target == bot {
+        return Err(anyhow!("I can't sanction myself.").into());
+    }
+    if target == ctx.author().id {
+        return Err(anyhow!("You can't sanction yourself.").into());
+    }
+    if target == guild.owner_id {
+        return Err(anyhow!("The owner of this guild can't be sanctioned.").into());
+    }
+
+    let target = match fetch_member(ctx, guild, target).await? {
+        Some(member) => member,
+        // users that aren't members have no roles, so there is no hierarchy to check
+        None => return Ok(()),
+    };
+    let target_position = role_position(ctx, &target);
+
+    // the owner is above every role
+    if ctx.author().id != guild.owner_id {
+        let author = fetch_member(ctx, guild, ctx.author().id)
+            .await?
+            .ok_or_else(|| anyhow!("You are not a member of this guild."))?;
+        if target_position >= role_position(ctx, &author) {
+            return Err(anyhow!(
+                "<@{}> has a role that is equal to or higher than yours.",
+                target.user.id
+            )
+            .into());
+        }
+    }
+
+    let me = fetch_member(ctx, guild, bot)
+        .await?
+        .ok_or_else(|| anyhow!("I'm not a member of this guild."))?;
+    if target_position >= role_position(ctx, &me) {
+        return Err(anyhow!(
+            "<@{}> has a role that is equal to or higher than mine.",
+            target.user.id
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Fetch a member of `guild` and return [`None`] if `user` is not a member
+async fn fetch_member(ctx: Context<'_>, guild: &Guild, user: UserId) -> Result<Option<Member>> {
+    match guild.member(ctx.discord(), user).await {
+        Ok(member) => Ok(Some(member)),
+        Err(SerenityError::Http(e)) if e.status_code().map(|s| s.as_u16()) == Some(404) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Position of the highest role of a member. Members without roles are at the
+/// very bottom.
+fn role_position(ctx: Context<'_>, member: &Member) -> i64 {
+    member
+        .highest_role_info(ctx.discord())
+        .map(|(_, position)| position)
+        .unwrap_or(0)
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use comfy_table::{presets::NOTHING, Table};
+use poise::serenity_prelude::utils::shard_id;
+
+use crate::{Context, Result};
+
+/// Whether the author is one of the owners in the current config
+///
+/// Unlike poise's `owners_only`, this picks up owners that were added by
+/// reloading the config.
+pub async fn is_owner(ctx: Context<'_>) -> Result<bool> {
+    Ok(ctx
+        .data()
+        .config
+        .get()
+        .discord
+        .bot_owners
+        .contains(&ctx.author().id))
+}
+
+/// Load the config file again without restarting
+#[command(
+    slash_command,
+    prefix_command,
+    rename = "reload-config",
+    check = "is_owner",
+    hide_in_help,
+    ephemeral
+)]
+pub async fn reload_config(ctx: Context<'_>) -> Result<()> {
+    ctx.data()
+        .config
+        .reload()
+        .map_err(|e| anyhow!("The config wasn't reloaded: {}", e))?;
+    ctx.say("Reloaded the config.").await?;
+    Ok(())
+}
+
+/// Show the status and latency of the shards of this process
+#[command(
+    slash_command,
+    prefix_command,
+    check = "is_owner",
+    hide_in_help,
+    ephemeral
+)]
+pub async fn shards(ctx: Context<'_>) -> Result<()> {
+    let cache = &ctx.discord().cache;
+    let total = cache.shard_count();
+    let mut guilds = HashMap::<u64, usize>::new();
+    for guild in cache.guilds() {
+        *guilds.entry(shard_id(guild, total)).or_default() += 1;
+    }
+
+    let mut table = Table::new();
+    table.load_preset(NOTHING);
+    table.set_header(["Shard", "Status", "Latency", "Guilds"]);
+    let shard_manager = ctx.framework().shard_manager.lock().await;
+    let runners = shard_manager.runners.lock().await;
+    let mut ids = runners.keys().copied().collect::<Vec<_>>();
+    ids.sort_by_key(|id| id.0);
+    for (id, runner) in ids.into_iter().map(|id| (id, &runners[&id])) {
+        table.add_row([
+            id.0.to_string(),
+            runner.stage.to_string(),
+            runner
+                .latency
+                .map(|l| format!("{}ms", l.as_millis()))
+                .unwrap_or_else(|| "-".to_string()),
+            guilds.get(&id.0).copied().unwrap_or(0).to_string(),
+        ]);
+    }
+    ctx.say(format!(
+        "This process runs {} of {} shard(s).\n```\n{}\n```",
+        runners.len(),
+        total,
+        table
+    ))
+    .await?;
+    Ok(())
+}
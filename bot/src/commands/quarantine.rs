@@ -0,0 +1,79 @@
+use poise::serenity_prelude::Member;
+
+use crate::{
+    commands::moderation::validate_target, maintenance::allows_moderation, modlog::Case,
+    quarantine::Quarantine, Context, Result,
+};
+
+/// Manage quarantined members
+#[command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_ROLES",
+    required_bot_permissions = "MANAGE_ROLES",
+    subcommands("add", "release")
+)]
+pub async fn quarantine(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Replace the roles of a member with the quarantine role
+#[command(slash_command, ephemeral, check = "allows_moderation")]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "The member you want to quarantine"] member: Member,
+    #[description = "Why the member is quarantined"] reason: Option<String>,
+) -> Result<()> {
+    let guild = ctx.guild().unwrap();
+    validate_target(ctx, &guild, member.user.id).await?;
+
+    let reason = reason.unwrap_or_else(|| format!("Quarantined by {}", ctx.author().tag()));
+    let case = Quarantine::apply(
+        ctx.discord(),
+        ctx.data(),
+        guild.id,
+        member.user.id,
+        ctx.author().id,
+        &reason,
+    )
+    .await?;
+    ctx.say(format!(
+        "Quarantined <@{}>.{}",
+        member.user.id,
+        case_suffix(case)
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Give a quarantined member their roles back
+#[command(slash_command, ephemeral)]
+pub async fn release(
+    ctx: Context<'_>,
+    #[description = "The member you want to release"] member: Member,
+    #[description = "Why the member is released"] reason: Option<String>,
+) -> Result<()> {
+    let case = Quarantine::release(
+        ctx.discord(),
+        ctx.data(),
+        ctx.guild_id().unwrap(),
+        member.user.id,
+        ctx.author().id,
+        reason.as_deref(),
+    )
+    .await?;
+    ctx.say(format!(
+        "Released <@{}> from quarantine.{}",
+        member.user.id,
+        case_suffix(case)
+    ))
+    .await?;
+    Ok(())
+}
+
+fn case_suffix(case: Option<Case>) -> String {
+    match case {
+        Some(case) => format!(" Case #{}.", case.id),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,69 @@
+use chrono::Utc;
+
+use crate::{raid::RaidGuard, util::format_duration, Context, Result};
+
+/// Manage raid mode
+#[command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("on", "off", "status")
+)]
+pub async fn raidmode(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Enable raid mode
+#[command(slash_command)]
+pub async fn on(ctx: Context<'_>) -> Result<()> {
+    let reason = format!("Enabled by {}", ctx.author().tag());
+    match RaidGuard::start(
+        ctx.discord(),
+        ctx.data(),
+        ctx.guild_id().unwrap(),
+        reason,
+        None,
+    )
+    .await
+    {
+        true => ctx.say("Raid mode is enabled.").await?,
+        false => ctx.say("Raid mode is already enabled.").await?,
+    };
+    Ok(())
+}
+
+/// Disable raid mode
+#[command(slash_command)]
+pub async fn off(ctx: Context<'_>) -> Result<()> {
+    let reason = format!("Disabled by {}", ctx.author().tag());
+    match RaidGuard::end(ctx.discord(), ctx.data(), ctx.guild_id().unwrap(), &reason).await {
+        true => ctx.say("Raid mode is disabled.").await?,
+        false => ctx.say("Raid mode is not enabled.").await?,
+    };
+    Ok(())
+}
+
+/// Show whether raid mode is enabled
+#[command(slash_command, ephemeral)]
+pub async fn status(ctx: Context<'_>) -> Result<()> {
+    let raid = ctx
+        .data()
+        .raids
+        .lock()
+        .unwrap()
+        .get(ctx.guild_id().unwrap())
+        .cloned();
+    match raid {
+        Some(raid) => {
+            ctx.say(format!(
+                "Raid mode is enabled since {} ({}), {} member(s) joined since then.",
+                format_duration(Utc::now() - raid.started),
+                raid.reason,
+                raid.joins
+            ))
+            .await?
+        }
+        None => ctx.say("Raid mode is not enabled.").await?,
+    };
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use futures::{future, stream, Stream, StreamExt};
+use poise::{serenity_prelude::Color, Modal};
+
+use crate::{
+    template::{Template, DEFAULT_LOCALE, LOCALES, PLACEHOLDERS},
+    ApplicationContext, Context, Result,
+};
+
+/// Manage the notifications members get when they are sanctioned
+#[command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("set", "reset", "show")
+)]
+pub async fn template(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[derive(Debug, Modal)]
+#[name = "Sanction notification"]
+struct TemplateModal {
+    #[name = "Title"]
+    #[max_length = 256]
+    title: Option<String>,
+    #[name = "Text"]
+    #[placeholder = "Placeholders: {username} {reason} {penalty} {guild} {appeal}"]
+    #[paragraph]
+    #[max_length = 4000]
+    body: String,
+    #[name = "Footer"]
+    #[max_length = 2048]
+    footer: Option<String>,
+    #[name = "Appeal text, replaces {appeal}"]
+    #[paragraph]
+    #[max_length = 1000]
+    appeal: Option<String>,
+}
+
+/// Set the notification for a locale
+#[command(slash_command, ephemeral)]
+pub async fn set(
+    ctx: ApplicationContext<'_>,
+    #[description = "The Discord locale, e.g. `en-US` or `de`"]
+    #[autocomplete = "autocomplete_locale"]
+    locale: String,
+) -> Result<()> {
+    if !LOCALES.contains(&locale.as_str()) {
+        return Err(anyhow!("`{}` is not a Discord locale.", locale).into());
+    }
+    let guild = ctx.interaction.guild_id().unwrap();
+
+    let current = stored_template(ctx.into(), &locale)
+        .await?
+        .unwrap_or_else(Template::fallback);
+    let modal = TemplateModal::execute_with_defaults(
+        ctx,
+        TemplateModal {
+            title: current.title,
+            body: current.body,
+            footer: current.footer,
+            appeal: current.appeal,
+        },
+    )
+    .await?;
+    let template = Template {
+        title: modal.title,
+        body: modal.body,
+        footer: modal.footer,
+        appeal: modal.appeal,
+    };
+    let preview = template
+        .preview()
+        .map_err(|e| anyhow!("Invalid template: {}", e))?;
+
+    sqlx::query!(
+        r#"
+    INSERT INTO sanction_templates (guild, locale, title, body, footer, appeal)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    ON CONFLICT (guild, locale) DO UPDATE
+    SET title = EXCLUDED.title,
+    body = EXCLUDED.body,
+    footer = EXCLUDED.footer,
+    appeal = EXCLUDED.appeal
+    "#,
+        guild.0.to_string(),
+        locale,
+        template.title,
+        template.body,
+        template.footer,
+        template.appeal,
+    )
+    .execute(&ctx.data.pool)
+    .await?;
+
+    send_preview(
+        ctx.into(),
+        format!("Saved the notification for `{}`:", locale),
+        preview,
+    )
+    .await
+}
+
+/// Remove the notification of a locale
+#[command(slash_command, ephemeral)]
+pub async fn reset(
+    ctx: Context<'_>,
+    #[description = "The Discord locale, e.g. `en-US` or `de`"]
+    #[autocomplete = "autocomplete_locale"]
+    locale: String,
+) -> Result<()> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM sanction_templates WHERE guild = $1 AND locale = $2"#,
+        ctx.guild_id().unwrap().0.to_string(),
+        locale,
+    )
+    .execute(&ctx.data().pool)
+    .await?
+    .rows_affected();
+
+    match deleted {
+        0 => {
+            ctx.say(format!("There is no notification for `{}`.", locale))
+                .await?
+        }
+        _ => {
+            ctx.say(format!("Removed the notification for `{}`.", locale))
+                .await?
+        }
+    };
+    Ok(())
+}
+
+/// Show the notification of a locale with example values
+#[command(slash_command, ephemeral)]
+pub async fn show(
+    ctx: Context<'_>,
+    #[description = "The Discord locale, e.g. `en-US` or `de`"]
+    #[autocomplete = "autocomplete_locale"]
+    locale: Option<String>,
+) -> Result<()> {
+    let locale = locale.unwrap_or_else(|| DEFAULT_LOCALE.to_string());
+    let (source, template) = match stored_template(ctx, &locale).await? {
+        Some(template) => ("", template),
+        None => (
+            " (not configured, the default is used)",
+            Template::fallback(),
+        ),
+    };
+    let preview = template
+        .preview()
+        .map_err(|e| anyhow!("The notification for `{}` is invalid: {}", locale, e))?;
+    send_preview(
+        ctx,
+        format!(
+            "Notification for `{}`{}. Available placeholders: {}",
+            locale,
+            source,
+            PLACEHOLDERS
+                .iter()
+                .map(|p| format!("`{{{}}}`", p))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        preview,
+    )
+    .await
+}
+
+/// The template of a locale from the database or the config file
+async fn stored_template(ctx: Context<'_>, locale: &str) -> Result<Option<Template>> {
+    let guild = ctx.guild_id().unwrap();
+    let stored = sqlx::query!(
+        r#"
+    SELECT title, body, footer, appeal FROM sanction_templates
+    WHERE guild = $1 AND locale = $2
+    "#,
+        guild.0.to_string(),
+        locale,
+    )
+    .fetch_optional(&ctx.data().pool)
+    .await?
+    .map(|row| Template {
+        title: row.title,
+        body: row.body,
+        footer: row.footer,
+        appeal: row.appeal,
+    });
+    Ok(stored.or_else(|| ctx.data().guild(guild).templates.remove(locale)))
+}
+
+async fn send_preview(ctx: Context<'_>, content: String, preview: Template) -> Result<()> {
+    ctx.send(|reply| {
+        reply.content(content);
+        reply.embed(|e| {
+            if let Some(title) = preview.title {
+                e.title(title);
+            }
+            e.color(Color::RED);
+            e.description(preview.body);
+            if let Some(footer) = preview.footer {
+                e.footer(|f| f.text(footer));
+            }
+            e
+        });
+        reply.ephemeral(true)
+    })
+    .await?;
+    Ok(())
+}
+
+async fn autocomplete_locale<'a>(
+    _: Context<'a>,
+    partial: &'a str,
+) -> impl Stream<Item = String> + 'a {
+    let partial = partial.to_lowercase();
+    stream::iter(LOCALES)
+        .filter(move |locale| future::ready(locale.to_lowercase().starts_with(&partial)))
+        .map(|locale| locale.to_string())
+}
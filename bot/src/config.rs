@@ -1,17 +1,151 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
 
-use poise::serenity_prelude::UserId;
-use secrecy::SecretString;
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 use serde_with::{serde_as, DisplayFromStr};
 use tracing::Level;
 
+use crate::{
+    automod::AutomodConfig,
+    http::HttpConfig,
+    logging::{self, LogHandle},
+    messagelog::MessageLogConfig,
+    quarantine::{QuarantineConfig, Remedy},
+    raid::RaidConfig,
+    screening::ScreeningConfig,
+    shutdown::ShutdownConfig,
+    template::Template,
+    verification::VerificationConfig,
+};
+
+#[serde_as]
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub database: Database,
     #[serde(default)]
     pub tracing: Tracing,
     pub discord: Discord,
+    /// The listener for monitoring, disabled if not set. Only takes effect
+    /// after a restart.
+    #[serde(default)]
+    pub http: Option<HttpConfig>,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// Maintenance mode in all guilds, see [`crate::maintenance`]
+    #[serde(default)]
+    pub maintenance: bool,
+    #[serde_as(as = "HashMap<DisplayFromStr, _>")]
+    #[serde(default)]
+    pub guilds: HashMap<GuildId, GuildConfig>,
+}
+
+impl Config {
+    /// Load the config from `Bot.toml` and the `PWNHUB_BOT_` environment
+    /// variables and validate it
+    pub fn load() -> anyhow::Result<Self> {
+        let config: Self = Figment::new()
+            .merge(Toml::file("Bot.toml"))
+            .merge(Env::prefixed("PWNHUB_BOT_").map(|k| k.as_str().replace('_', ".").into()))
+            .extract()
+            .map_err(|e| anyhow!("Failed to load configuration: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check for values that can be parsed but make no sense
+    pub fn validate(&self) -> anyhow::Result<()> {
+        logging::filter(&self.tracing)?;
+        if self.discord.prefix.is_empty() {
+            return Err(anyhow!("`discord.prefix` must not be empty"));
+        }
+        for (id, guild) in &self.guilds {
+            guild
+                .validate()
+                .map_err(|e| anyhow!("Invalid settings for guild {}: {}", id, e))?;
+        }
+        Ok(())
+    }
+
+    /// The settings of a guild, or the defaults if the guild isn't configured
+    pub fn guild(&self, guild: GuildId) -> GuildConfig {
+        self.guilds.get(&guild).cloned().unwrap_or_default()
+    }
+}
+
+/// The config of the running bot, which can be swapped for a new one
+#[derive(Debug, Clone)]
+pub struct ConfigHandle {
+    config: Arc<RwLock<Arc<Config>>>,
+    log_filter: Option<LogHandle>,
+}
+
+impl ConfigHandle {
+    /// `log_filter` is updated whenever the config is reloaded
+    pub fn new(config: Config, log_filter: Option<LogHandle>) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            log_filter,
+        }
+    }
+
+    /// The current config
+    pub fn get(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Load the config again and swap it in
+    ///
+    /// The current config is kept if the new one is invalid or changes one of
+    /// the secrets, since the database connection and the gateway connection
+    /// can't be changed while the bot is running.
+    #[instrument(skip_all, name = "config_reload", level = "info")]
+    pub fn reload(&self) -> anyhow::Result<()> {
+        self.replace(Config::load()?)
+    }
+
+    /// Swap in `config`, unless it changes one of the secrets
+    fn replace(&self, config: Config) -> anyhow::Result<()> {
+        let current = self.get();
+        if config.database.url.expose_secret() != current.database.url.expose_secret() {
+            return Err(anyhow!(
+                "`database.url` can't be changed while the bot is running"
+            ));
+        }
+        if config.discord.token.expose_secret() != current.discord.token.expose_secret() {
+            return Err(anyhow!(
+                "`discord.token` can't be changed while the bot is running"
+            ));
+        }
+
+        if config.tracing.format != current.tracing.format
+            || config.tracing.file != current.tracing.file
+        {
+            event!(
+                Level::WARN,
+                "the log format and the log file only change after a restart"
+            );
+        }
+        if config.discord.shards != current.discord.shards {
+            event!(Level::WARN, "the shards only change after a restart");
+        }
+        if let Some(log_filter) = &self.log_filter {
+            log_filter
+                .reload(logging::filter(&config.tracing)?)
+                .map_err(|e| anyhow!("Failed to change the log filter: {}", e))?;
+        }
+        *self.config.write().unwrap() = Arc::new(config);
+        event!(Level::INFO, "reloaded the configuration");
+        Ok(())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -22,19 +156,73 @@ pub struct Database {
 #[serde_as]
 #[derive(Debug, Deserialize, Clone)]
 pub struct Tracing {
+    /// The level of everything that isn't matched by `filter`
     #[serde_as(as = "DisplayFromStr")]
     #[serde(default = "default_log_level")]
     pub level: Level,
+    /// Directives in the format of `RUST_LOG`, e.g.
+    /// `pwnhub_bot::invite=debug,serenity=warn`
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Only takes effect after a restart
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Also write logs to a file. Only takes effect after a restart.
+    #[serde(default)]
+    pub file: Option<LogFile>,
 }
 
 impl Default for Tracing {
     fn default() -> Self {
         Self {
             level: default_log_level(),
+            filter: None,
+            format: LogFormat::default(),
+            file: None,
         }
     }
 }
 
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, one line per event
+    #[default]
+    Full,
+    /// Human readable, multiple lines per event
+    Pretty,
+    /// Like `full`, but shorter
+    Compact,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct LogFile {
+    /// The directory the log files are written to
+    pub directory: PathBuf,
+    /// The name of the log files, followed by the date if they are rotated
+    #[serde(default = "default_log_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub rotation: LogRotation,
+}
+
+fn default_log_prefix() -> String {
+    "pwnhub-bot.log".to_string()
+}
+
+/// How often a new log file is started
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
 fn default_log_level() -> Level {
     match cfg!(debug_assertions) {
         true => Level::DEBUG,
@@ -52,8 +240,321 @@ pub struct Discord {
     // rename to `owners` because underscores dont work in env variables
     #[serde(default, rename = "owners")]
     pub bot_owners: HashSet<UserId>,
+    /// Which shards this process runs. Only takes effect after a restart.
+    #[serde(default)]
+    pub shards: ShardMode,
+}
+
+/// The shards of a process
+///
+/// `shards = "auto"` lets Discord pick the number of shards, `shards = 4` runs
+/// four shards and `shards = { first = 0, last = 3, total = 8 }` runs half of
+/// eight shards, so the other half can run in a second process.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "RawShardMode")]
+pub enum ShardMode {
+    #[default]
+    Auto,
+    Fixed(u64),
+    /// The shards `first` to `last`, both included, of `total` shards
+    Range {
+        first: u64,
+        last: u64,
+        total: u64,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AutoShards {
+    Auto,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawShardMode {
+    Auto(AutoShards),
+    Fixed(u64),
+    Range { first: u64, last: u64, total: u64 },
+}
+
+impl TryFrom<RawShardMode> for ShardMode {
+    type Error = String;
+
+    fn try_from(raw: RawShardMode) -> Result<Self, Self::Error> {
+        match raw {
+            RawShardMode::Auto(AutoShards::Auto) => Ok(Self::Auto),
+            RawShardMode::Fixed(0) => Err("at least one shard is needed".to_string()),
+            RawShardMode::Fixed(total) => Ok(Self::Fixed(total)),
+            RawShardMode::Range { first, last, total } if first > last || last >= total => {
+                Err(format!(
+                    "shards {} to {} are not within {} shards",
+                    first, last, total
+                ))
+            }
+            RawShardMode::Range { first, last, total } => Ok(Self::Range { first, last, total }),
+        }
+    }
 }
 
 fn default_prefix() -> String {
     "?".to_string()
 }
+
+/// Settings that only apply to a single guild
+#[serde_as]
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct GuildConfig {
+    /// The channel moderation actions are logged to
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub modlog: Option<ChannelId>,
+    /// The channel appeals are posted to. Sanctions can't be appealed if this
+    /// isn't set.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub appeals: Option<ChannelId>,
+    /// Sanction notification templates by locale
+    #[serde(default)]
+    pub templates: HashMap<String, Template>,
+    #[serde(default)]
+    pub automod: AutomodConfig,
+    /// Raid detection, disabled if not set. Raid mode can still be enabled
+    /// manually.
+    #[serde(default)]
+    pub raid: Option<RaidConfig>,
+    /// Checks for new members, disabled if not set
+    #[serde(default)]
+    pub screening: Option<ScreeningConfig>,
+    /// Verification of new members, disabled if not set
+    #[serde(default)]
+    pub verification: Option<VerificationConfig>,
+    /// Members can't be quarantined if this isn't set
+    #[serde(default)]
+    pub quarantine: Option<QuarantineConfig>,
+    /// What happens to members whose invite can't be tracked. They are kicked
+    /// if they should be quarantined but no quarantine role is configured.
+    #[serde(default)]
+    pub untracked: Remedy,
+    /// The channel joins, leaves, role changes and bans are posted to
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub auditlog: Option<ChannelId>,
+    /// Logging of edited and deleted messages, disabled if not set
+    #[serde(default)]
+    pub messagelog: Option<MessageLogConfig>,
+    /// Maintenance mode in this guild, see [`crate::maintenance`]
+    #[serde(default)]
+    pub maintenance: bool,
+}
+
+impl GuildConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        // Discord doesn't allow longer timeouts
+        const MAX_TIMEOUT: i64 = 28 * 24 * 60 * 60;
+
+        let automod = &self.automod;
+        for (rule, settings) in [
+            ("sticker", &automod.sticker.settings),
+            ("spam", &automod.spam.settings),
+            ("invites", &automod.invites.settings),
+            ("links", &automod.links.settings),
+            ("filter", &automod.filter.settings),
+        ] {
+            if !(1..=MAX_TIMEOUT).contains(&settings.timeout) {
+                return Err(anyhow!(
+                    "the timeout of the {} rule must be between 1 second and 28 days",
+                    rule
+                ));
+            }
+            if settings.escalation < 1.0 {
+                return Err(anyhow!(
+                    "the escalation of the {} rule must be at least 1",
+                    rule
+                ));
+            }
+        }
+        let spam = &automod.spam.options;
+        if spam.messages == 0 || spam.interval <= 0 {
+            return Err(anyhow!(
+                "the spam rule needs at least one message per interval"
+            ));
+        }
+
+        if let Some(raid) = &self.raid {
+            if raid.joins == 0 || raid.invite_joins == 0 || raid.interval <= 0 {
+                return Err(anyhow!(
+                    "raid detection needs at least one join per interval"
+                ));
+            }
+            if !(1..=MAX_TIMEOUT).contains(&raid.quarantine) {
+                return Err(anyhow!(
+                    "the raid quarantine must be between 1 second and 28 days"
+                ));
+            }
+        }
+        if let Some(screening) = &self.screening {
+            if !(1..=MAX_TIMEOUT).contains(&screening.quarantine) {
+                return Err(anyhow!(
+                    "the screening quarantine must be between 1 second and 28 days"
+                ));
+            }
+        }
+        if let Some(verification) = &self.verification {
+            if verification.timeout <= 0 {
+                return Err(anyhow!("the verification timeout must be positive"));
+            }
+            if verification.member == Some(verification.unverified) {
+                return Err(anyhow!(
+                    "the unverified role and the member role must be different"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRETS: &str = r#"
+    [database]
+    url = "postgres://localhost/bot"
+    [discord]
+    token = "token"
+    "#;
+
+    fn guild(toml: &str) -> GuildConfig {
+        Figment::from(Toml::string(toml)).extract().unwrap()
+    }
+
+    fn config(toml: &str) -> Config {
+        Figment::from(Toml::string(toml)).extract().unwrap()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(GuildConfig::default().validate().is_ok());
+        assert!(config(SECRETS).validate().is_ok());
+    }
+
+    #[test]
+    fn rule_timeout_bounds() {
+        assert!(guild("automod.spam.timeout = 1").validate().is_ok());
+        assert!(guild("automod.spam.timeout = 2419200").validate().is_ok());
+        assert!(guild("automod.spam.timeout = 0").validate().is_err());
+        // longer than 28 days
+        assert!(guild("automod.links.timeout = 2419201").validate().is_err());
+    }
+
+    #[test]
+    fn rule_escalation_bounds() {
+        assert!(guild("automod.filter.escalation = 1.0").validate().is_ok());
+        assert!(guild("automod.filter.escalation = 0.5").validate().is_err());
+    }
+
+    #[test]
+    fn spam_needs_messages() {
+        assert!(guild("automod.spam.messages = 0").validate().is_err());
+        assert!(guild("automod.spam.interval = 0").validate().is_err());
+    }
+
+    #[test]
+    fn raid_bounds() {
+        assert!(guild("[raid]").validate().is_ok());
+        assert!(guild("raid.joins = 0").validate().is_err());
+        assert!(guild("raid.invitejoins = 0").validate().is_err());
+        assert!(guild("raid.interval = 0").validate().is_err());
+        assert!(guild("raid.quarantine = 0").validate().is_err());
+        assert!(guild("raid.quarantine = 2419201").validate().is_err());
+    }
+
+    #[test]
+    fn screening_bounds() {
+        assert!(guild("[screening]").validate().is_ok());
+        assert!(guild("screening.quarantine = 0").validate().is_err());
+    }
+
+    #[test]
+    fn verification_bounds() {
+        let verification = r#"
+        [verification]
+        unverified = "1"
+        channel = "2"
+        "#;
+        assert!(guild(verification).validate().is_ok());
+        assert!(guild(&format!("{}timeout = 0", verification))
+            .validate()
+            .is_err());
+        assert!(guild(&format!("{}member = \"1\"", verification))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_guilds_are_named() {
+        let error = config(&format!("{}[guilds.123]\nraid.joins = 0", SECRETS))
+            .validate()
+            .unwrap_err();
+        assert!(error.to_string().contains("123"));
+    }
+
+    fn shards(value: &str) -> Option<ShardMode> {
+        Figment::from(Toml::string(&format!("shards = {}", value)))
+            .extract_inner("shards")
+            .ok()
+    }
+
+    #[test]
+    fn auto_shards() {
+        assert_eq!(shards(r#""auto""#).unwrap(), ShardMode::Auto);
+        assert_eq!(ShardMode::default(), ShardMode::Auto);
+        assert!(shards(r#""all""#).is_none());
+    }
+
+    #[test]
+    fn fixed_shards() {
+        assert_eq!(shards("4").unwrap(), ShardMode::Fixed(4));
+        assert!(shards("0").is_none());
+    }
+
+    #[test]
+    fn shard_range() {
+        assert_eq!(
+            shards("{ first = 0, last = 3, total = 8 }").unwrap(),
+            ShardMode::Range {
+                first: 0,
+                last: 3,
+                total: 8
+            }
+        );
+        assert_eq!(
+            shards("{ first = 7, last = 7, total = 8 }").unwrap(),
+            ShardMode::Range {
+                first: 7,
+                last: 7,
+                total: 8
+            }
+        );
+        // first > last
+        assert!(shards("{ first = 4, last = 3, total = 8 }").is_none());
+        // last >= total
+        assert!(shards("{ first = 0, last = 8, total = 8 }").is_none());
+        assert!(shards("{ first = 0, last = 3 }").is_none());
+    }
+
+    #[test]
+    fn secrets_cant_change() {
+        let handle = ConfigHandle::new(config(SECRETS), None);
+        let url = SECRETS.replace("localhost/bot", "localhost/other");
+        assert!(handle.replace(config(&url)).is_err());
+        let token = SECRETS.replace("\"token\"", "\"other\"");
+        assert!(handle.replace(config(&token)).is_err());
+        assert!(!handle.get().maintenance);
+
+        let maintenance = format!("maintenance = true\n{}", SECRETS);
+        assert!(handle.replace(config(&maintenance)).is_ok());
+        assert!(handle.get().maintenance);
+    }
+}
@@ -1,18 +1,71 @@
-use poise::serenity_prelude::TypeMapKey;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{GuildId, TypeMapKey};
 use sqlx::PgPool;
 
-use crate::Config;
+use crate::{
+    automod::{FilterStore, SpamTracker},
+    config::{ConfigHandle, GuildConfig},
+    maintenance::Maintenance,
+    messagelog::MessageCache,
+    raid::RaidTracker,
+    settings::SettingsStore,
+    shutdown::Shutdown,
+};
 
 #[derive(Debug, Clone)]
 #[non_exhaustive]
 pub struct Data {
     pub pool: PgPool,
-    pub config: Config,
+    pub config: ConfigHandle,
+    /// Recent messages of members for the spam rule
+    pub spam: Arc<Mutex<SpamTracker>>,
+    /// Compiled content filters
+    pub filters: FilterStore,
+    /// Recent joins and ongoing raids
+    pub raids: Arc<Mutex<RaidTracker>>,
+    /// Recent messages for the message log
+    pub messages: Arc<Mutex<MessageCache>>,
+    /// Guild settings that were changed with `/config`
+    pub settings: SettingsStore,
+    /// Running work and the signal to stop
+    pub shutdown: Shutdown,
+    pub maintenance: Maintenance,
+    /// When the bot was started
+    pub started: DateTime<Utc>,
 }
 
 impl Data {
-    pub const fn new(pool: PgPool, config: Config) -> Self {
-        Self { pool, config }
+    pub fn new(pool: PgPool, config: ConfigHandle) -> Self {
+        Self {
+            pool,
+            config,
+            spam: Arc::default(),
+            filters: FilterStore::default(),
+            raids: Arc::default(),
+            messages: Arc::default(),
+            settings: SettingsStore::default(),
+            shutdown: Shutdown::default(),
+            maintenance: Maintenance::default(),
+            started: Utc::now(),
+        }
+    }
+
+    /// The settings of a guild: the config file, overridden by the settings
+    /// that were changed with `/config`
+    pub fn guild(&self, guild: GuildId) -> GuildConfig {
+        let mut config = self.config.get().guild(guild);
+        self.settings.get(guild).apply(&mut config);
+        config
+    }
+
+    /// Whether automatic actions and moderation commands are paused in
+    /// `guild`
+    pub fn in_maintenance(&self, guild: GuildId) -> bool {
+        self.maintenance.is_enabled()
+            || self.config.get().maintenance
+            || self.guild(guild).maintenance
     }
 }
 
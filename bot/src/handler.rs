@@ -1,11 +1,11 @@
 use std::{fmt::Debug, sync::Arc};
 
-use chrono::{Duration, Utc};
 use poise::{
     dispatch_event,
     serenity_prelude::{
-        Context, EventHandler, Guild, Interaction, InviteCreateEvent, InviteDeleteEvent, Member,
-        Message, Ready, ShardManager, StickerFormatType, UnavailableGuild, UserId,
+        ChannelId, Context, EventHandler, Guild, GuildId, Interaction, InviteCreateEvent,
+        InviteDeleteEvent, Member, Message, MessageId, MessageUpdateEvent, Ready, ShardManager,
+        UnavailableGuild, User, UserId,
     },
     Event, FrameworkContext, FrameworkOptions,
 };
@@ -13,8 +13,18 @@ use tokio::sync::{Mutex, RwLock};
 use tracing::{Instrument, Level};
 
 use crate::{
+    appeal::Appeals,
+    auditlog::AuditLog,
+    automod::Automod,
     invite::{InviteStore, InviteTracker},
-    util::send_sanction_notification,
+    messagelog::MessageLog,
+    quarantine::Quarantine,
+    raid::RaidGuard,
+    screening::Screening,
+    shutdown::Shutdown,
+    template::remember_locale,
+    verification::Verification,
+    Data,
 };
 
 #[derive(Debug)]
@@ -23,6 +33,8 @@ pub struct GlobalEventHandler<D, E> {
     pub data: D,
     pub shard_manager: RwLock<Option<Arc<Mutex<ShardManager>>>>,
     pub whoami: RwLock<Option<UserId>>,
+    /// Events are handled to the end before the bot disconnects
+    pub shutdown: Shutdown,
 }
 
 impl<D, E> GlobalEventHandler<D, E>
@@ -51,7 +63,9 @@ where
 {
     #[instrument(skip_all)]
     async fn ready(&self, ctx: Context, ready: Ready) {
-        // this is only executed once at start up
+        let _work = self.shutdown.work();
+        // this is executed once for every shard, and again whenever a shard
+        // can't resume its session
         *self
             .whoami
             .write()
@@ -60,11 +74,23 @@ where
 
         event!(
             Level::INFO,
+            shard = ctx.shard_id,
             guilds = ready.guilds.len(),
-            "I'm on {} guilds!",
+            "shard {} is on {} guilds!",
+            ctx.shard_id,
             ready.guilds.len(),
         );
 
+        // only the guilds of this shard, the others are resumed by their own
+        // shard, which may run in another process
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        let resume_ctx = ctx.clone();
+        let guilds = ready.guilds.iter().map(|g| g.id).collect::<Vec<_>>();
+        tokio::spawn(async move {
+            Verification::resume(&resume_ctx, &data, &guilds).await;
+            RaidGuard::resume(&resume_ctx, &data, &guilds).await;
+        });
+
         self.dispatch_event(
             ctx,
             Event::Ready {
@@ -76,38 +102,107 @@ where
     }
 
     #[instrument(skip_all)]
-    #[allow(unused_must_use)]
     async fn message(&self, ctx: Context, new_message: Message) {
-        // prevent sending of default stickers
-        if !new_message.is_private() {
-            for sticker in &new_message.sticker_items {
-                if sticker.format_type == StickerFormatType::Lottie {
-                    let comms_disabled_until = Utc::now() + Duration::minutes(1);
-                    send_sanction_notification(
-                        &ctx,
-                        &new_message.author,
-                        "sending a default sticker",
-                        crate::util::Penalty::Timeout(comms_disabled_until),
-                    )
-                    .await;
-                    new_message
-                        .member(&ctx)
-                        .await
-                        .unwrap()
-                        .disable_communication_until_datetime(&ctx, comms_disabled_until.into())
-                        .await;
-                    new_message.delete(&ctx).await;
-                }
-            }
-        }
+        let _work = self.shutdown.work();
+        // rules may need the data of the context themselves, so it must not be
+        // locked while they run
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        MessageLog::on_message(&ctx, &data, &new_message);
+        Automod::on_message(&ctx, &data, &new_message).await;
 
         self.dispatch_event(ctx, Event::Message { new_message })
             .instrument(debug_span!("dispatch_message_event"))
             .await;
     }
 
+    #[instrument(skip_all)]
+    async fn message_update(
+        &self,
+        ctx: Context,
+        old_if_available: Option<Message>,
+        _: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        let _work = self.shutdown.work();
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        MessageLog::on_update(&ctx, &data, old_if_available, &event).await;
+    }
+
+    #[instrument(skip_all)]
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        channel: ChannelId,
+        message: MessageId,
+        guild: Option<GuildId>,
+    ) {
+        let _work = self.shutdown.work();
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        MessageLog::on_delete(&ctx, &data, guild, channel, message).await;
+    }
+
+    #[instrument(skip_all)]
+    async fn message_delete_bulk(
+        &self,
+        ctx: Context,
+        channel: ChannelId,
+        messages: Vec<MessageId>,
+        guild: Option<GuildId>,
+    ) {
+        let _work = self.shutdown.work();
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        MessageLog::on_bulk_delete(&ctx, &data, guild, channel, &messages).await;
+    }
+
     #[instrument(skip_all)]
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let _work = self.shutdown.work();
+        let locale = match &interaction {
+            Interaction::ApplicationCommand(command) => Some((command.user.id, &command.locale)),
+            Interaction::MessageComponent(component) => {
+                Some((component.user.id, &component.locale))
+            }
+            Interaction::ModalSubmit(modal) => Some((modal.user.id, &modal.locale)),
+            _ => None,
+        };
+        if let Some((user, locale)) = locale {
+            let pool = ctx.data.read().await.get::<Data>().unwrap().pool.clone();
+            let locale = locale.clone();
+            self.shutdown.spawn(async move {
+                if let Err(e) = remember_locale(&pool, user, &locale).await {
+                    event!(Level::WARN, user = user.0, error = ?e, "failed to store locale of user {}: {}", user.0, e);
+                }
+            });
+        }
+
+        match &interaction {
+            Interaction::MessageComponent(component)
+                if Appeals::handles(&component.data.custom_id) =>
+            {
+                let reader = ctx.data.read().await;
+                Appeals::on_component(&ctx, reader.get::<Data>().unwrap(), component).await;
+                return;
+            }
+            Interaction::ModalSubmit(modal) if Appeals::handles(&modal.data.custom_id) => {
+                let reader = ctx.data.read().await;
+                Appeals::on_modal(&ctx, reader.get::<Data>().unwrap(), modal).await;
+                return;
+            }
+            Interaction::MessageComponent(component)
+                if Verification::handles(&component.data.custom_id) =>
+            {
+                let reader = ctx.data.read().await;
+                Verification::on_component(&ctx, reader.get::<Data>().unwrap(), component).await;
+                return;
+            }
+            Interaction::ModalSubmit(modal) if Verification::handles(&modal.data.custom_id) => {
+                let reader = ctx.data.read().await;
+                Verification::on_modal(&ctx, reader.get::<Data>().unwrap(), modal).await;
+                return;
+            }
+            _ => (),
+        }
+
         self.dispatch_event(ctx, Event::InteractionCreate { interaction })
             .instrument(debug_span!("dispatch_interaction_create_event"))
             .await;
@@ -115,26 +210,99 @@ where
 
     #[instrument(skip_all)]
     async fn invite_create(&self, ctx: Context, invite: InviteCreateEvent) {
+        let _work = self.shutdown.work();
         InviteStore::invite_created(&ctx, invite).await;
     }
 
     #[instrument(skip_all)]
     async fn guild_create(&self, ctx: Context, guild: Guild, _: bool) {
+        let _work = self.shutdown.work();
         InviteStore::invite_guild_created(ctx, &guild).await;
     }
 
     #[instrument(skip_all)]
     async fn guild_delete(&self, ctx: Context, guild: UnavailableGuild, _: Option<Guild>) {
+        let _work = self.shutdown.work();
         InviteStore::invite_guild_deleted(&ctx, &guild).await;
     }
 
     #[instrument(skip_all)]
     async fn invite_delete(&self, ctx: Context, invite: InviteDeleteEvent) {
+        let _work = self.shutdown.work();
         InviteStore::invite_deleted(&ctx, &invite).await;
     }
 
     #[instrument(skip_all)]
     async fn guild_member_addition(&self, ctx: Context, member: Member) {
-        InviteTracker::on_join(ctx, member).await;
+        let _work = self.shutdown.work();
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        let screening = match data.guild(member.guild_id).screening {
+            Some(config) if !member.user.bot => Some((Screening::screen(&config, &member), config)),
+            _ => None,
+        };
+
+        let attribution = InviteTracker::on_join(&ctx, &member).await;
+        AuditLog::on_join(&ctx, &data, &member, attribution.as_ref()).await;
+        if member.user.bot {
+            return;
+        }
+
+        Quarantine::on_join(&ctx, &data, &member).await;
+        // the raid detection has to see every join, so it runs before the
+        // screening can remove the member
+        if RaidGuard::on_join(
+            &ctx,
+            &data,
+            member.guild_id,
+            member.user.id,
+            attribution.as_ref(),
+        )
+        .await
+        {
+            return;
+        }
+        if let Some((screening, config)) = screening {
+            if screening
+                .enforce(&ctx, &data, &config, &member, attribution.as_ref())
+                .await
+            {
+                return;
+            }
+        }
+        Verification::on_join(&ctx, &data, &member).await;
+    }
+
+    #[instrument(skip_all)]
+    async fn guild_member_removal(
+        &self,
+        ctx: Context,
+        guild: GuildId,
+        user: User,
+        member: Option<Member>,
+    ) {
+        let _work = self.shutdown.work();
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        AuditLog::on_leave(&ctx, &data, guild, &user, member.as_ref()).await;
+    }
+
+    #[instrument(skip_all)]
+    async fn guild_member_update(&self, ctx: Context, old: Option<Member>, new: Member) {
+        let _work = self.shutdown.work();
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        AuditLog::on_update(&ctx, &data, old.as_ref(), &new).await;
+    }
+
+    #[instrument(skip_all)]
+    async fn guild_ban_addition(&self, ctx: Context, guild: GuildId, user: User) {
+        let _work = self.shutdown.work();
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        AuditLog::on_ban(&ctx, &data, guild, &user).await;
+    }
+
+    #[instrument(skip_all)]
+    async fn guild_ban_removal(&self, ctx: Context, guild: GuildId, user: User) {
+        let _work = self.shutdown.work();
+        let data = ctx.data.read().await.get::<Data>().unwrap().clone();
+        AuditLog::on_unban(&ctx, &data, guild, &user).await;
     }
 }
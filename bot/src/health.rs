@@ -0,0 +1,209 @@
+//! Health checks for container orchestration
+//!
+//! `/healthz` only tells whether the bot is alive, while `/readyz` checks
+//! everything the bot needs to moderate: the gateway, the invite store and
+//! the database.
+
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use serenity::gateway::ConnectionStage;
+use sqlx::Connection;
+use tracing::Level;
+
+use crate::{
+    http::HttpState,
+    invite::{FailedInviteLoads, InviteStore},
+};
+
+/// How long the database may take to answer a ping
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long all shards may be disconnected before the bot is considered dead,
+/// so it isn't restarted while the shards reconnect
+const DISCONNECT_GRACE: Duration = Duration::from_secs(2 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ready,
+    /// Ready, but some shards are disconnected, so events of their guilds are
+    /// missed, or the invites of some guilds couldn't be loaded
+    Degraded,
+    NotReady,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Ready => "ready",
+            Self::Degraded => "degraded",
+            Self::NotReady => "not ready",
+        }
+    }
+}
+
+/// The result of the checks, rendered as one line per check
+pub struct Report {
+    pub status: Status,
+    pub body: String,
+}
+
+/// Shards the shard manager started and how many of them are connected
+struct Shards {
+    total: usize,
+    connected: usize,
+}
+
+async fn shards(state: &HttpState) -> Shards {
+    let mut shards = Shards {
+        total: 0,
+        connected: 0,
+    };
+    if let Some(shard_manager) = state.handler.shard_manager.read().await.as_ref() {
+        let shard_manager = shard_manager.lock().await;
+        for runner in shard_manager.runners.lock().await.values() {
+            shards.total += 1;
+            if runner.stage == ConnectionStage::Connected {
+                shards.connected += 1;
+            }
+        }
+    }
+    shards
+}
+
+/// Whether the bot is alive
+///
+/// The bot is considered wedged once it was ready but none of its shards was
+/// connected for [`DISCONNECT_GRACE`], counted from the first check that
+/// found them disconnected.
+pub async fn live(state: &HttpState) -> Report {
+    let ready = state.handler.whoami.read().await.is_some();
+    let shards = shards(state).await;
+    if !ready || shards.connected > 0 {
+        *state.disconnected.lock().unwrap() = None;
+        return Report {
+            status: Status::Ready,
+            body: "ok\n".to_string(),
+        };
+    }
+
+    let since = *state
+        .disconnected
+        .lock()
+        .unwrap()
+        .get_or_insert_with(Instant::now);
+    let (status, verdict) = match since.elapsed() >= DISCONNECT_GRACE {
+        true => (Status::NotReady, "dead"),
+        false => (Status::Ready, "reconnecting"),
+    };
+    Report {
+        status,
+        body: format!(
+            "{}: 0/{} shards connected for {}s\n",
+            verdict,
+            shards.total,
+            since.elapsed().as_secs()
+        ),
+    }
+}
+
+/// Whether the bot is ready to moderate its guilds
+#[instrument(skip_all, name = "readiness_check", level = "debug")]
+pub async fn ready(state: &HttpState) -> Report {
+    let mut status = Status::Ready;
+    let mut body = String::new();
+
+    match *state.handler.whoami.read().await {
+        Some(user) => writeln!(body, "gateway: ok (logged in as {})", user.0),
+        None => {
+            status = Status::NotReady;
+            writeln!(body, "gateway: waiting for the ready event")
+        }
+    }
+    .unwrap();
+
+    let guilds = state.cache.guilds();
+    let (loaded, failed) = {
+        let typemap = state.typemap.read().await;
+        let store = match typemap.get::<InviteStore>() {
+            Some(store) => Some(store.read().await),
+            None => None,
+        };
+        let failures = typemap.get::<FailedInviteLoads>();
+        let mut loaded = 0;
+        let mut failed = 0;
+        for guild in &guilds {
+            if store
+                .as_ref()
+                .map(|s| s.contains_key(guild))
+                .unwrap_or(false)
+            {
+                loaded += 1;
+            } else if failures.map(|f| f.contains(guild)).unwrap_or(false) {
+                // guilds whose invites can't be loaded won't get ready by
+                // waiting
+                failed += 1;
+            }
+        }
+        (loaded, failed)
+    };
+    let invites = if loaded + failed < guilds.len() {
+        status = Status::NotReady;
+        "loading"
+    } else if failed > 0 {
+        "degraded"
+    } else {
+        "ok"
+    };
+    write!(
+        body,
+        "invites: {} ({}/{} guilds loaded",
+        invites,
+        loaded,
+        guilds.len()
+    )
+    .unwrap();
+    if failed > 0 {
+        write!(body, ", {} failed", failed).unwrap();
+    }
+    writeln!(body, ")").unwrap();
+
+    let ping = async {
+        let mut conn = state.handler.data.pool.acquire().await?;
+        conn.ping().await
+    };
+    match tokio::time::timeout(DATABASE_TIMEOUT, ping).await {
+        Ok(Ok(())) => writeln!(body, "database: ok"),
+        Ok(Err(e)) => {
+            event!(Level::WARN, error = ?e, "the database did not answer the readiness ping: {}", e);
+            status = Status::NotReady;
+            writeln!(body, "database: {}", e)
+        }
+        Err(_) => {
+            status = Status::NotReady;
+            writeln!(
+                body,
+                "database: no answer within {}s",
+                DATABASE_TIMEOUT.as_secs()
+            )
+        }
+    }
+    .unwrap();
+
+    let shards = shards(state).await;
+    if (shards.connected < shards.total || failed > 0) && status == Status::Ready {
+        status = Status::Degraded;
+    }
+    writeln!(
+        body,
+        "shards: {}/{} connected",
+        shards.connected, shards.total
+    )
+    .unwrap();
+
+    Report {
+        body: format!("{}\n{}", status.as_str(), body),
+        status,
+    }
+}
@@ -0,0 +1,105 @@
+//! HTTP listener for monitoring
+//!
+//! Serves the Prometheus metrics on `/metrics` and the health checks on
+//! `/healthz` and `/readyz`.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use poise::serenity_prelude::{Cache, TypeMap};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use tracing::Level;
+
+use crate::{
+    handler::GlobalEventHandler,
+    health::{self, Report, Status},
+    metrics::METRICS,
+    Data, Error,
+};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpConfig {
+    /// The address the listener binds to, e.g. `127.0.0.1:9100`
+    pub listen: SocketAddr,
+}
+
+/// Everything the listener reports on
+#[derive(Clone)]
+pub struct HttpState {
+    pub handler: Arc<GlobalEventHandler<Data, Error>>,
+    /// The data of the serenity client, which holds the invite store
+    pub typemap: Arc<RwLock<TypeMap>>,
+    pub cache: Arc<Cache>,
+    /// Since when none of the shards is connected, see [`health::live`]
+    pub disconnected: Arc<Mutex<Option<Instant>>>,
+}
+
+/// Bind to the configured address and serve requests until the bot shuts down
+pub async fn serve(config: &HttpConfig, state: HttpState) -> anyhow::Result<()> {
+    let shutdown = state.handler.data.shutdown.clone();
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, request).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&config.listen)
+        .map_err(|e| anyhow!("Cannot listen on {}: {}", config.listen, e))?
+        .serve(make_service)
+        .with_graceful_shutdown(async move { shutdown.started().await });
+    event!(Level::INFO, "listening on http://{}", config.listen);
+    server.await?;
+    Ok(())
+}
+
+#[instrument(skip_all, name = "http_request", level = "debug")]
+async fn handle(state: &HttpState, request: Request<Body>) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => match METRICS.gather(state).await {
+            Ok(metrics) => Response::builder()
+                .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(metrics.into())
+                .unwrap(),
+            Err(e) => {
+                event!(Level::ERROR, error = ?e, "failed to gather metrics: {}", e);
+                respond(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+        (&Method::GET, "/healthz") => report(health::live(state).await),
+        (&Method::GET, "/readyz") => report(health::ready(state).await),
+        _ => respond(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Degraded still counts as ready, the body tells the difference
+fn report(report: Report) -> Response<Body> {
+    let status = match report.status {
+        Status::Ready | Status::Degraded => StatusCode::OK,
+        Status::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(report.body.into())
+        .unwrap()
+}
+
+fn respond(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(status.canonical_reason().unwrap_or_default().into())
+        .unwrap()
+}
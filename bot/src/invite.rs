@@ -13,7 +13,7 @@ use poise::serenity_prelude::{
 use tokio::sync::RwLock;
 use tracing::{Instrument, Level};
 
-use crate::Data;
+use crate::{metrics::METRICS, quarantine::Remedy, Data};
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub struct Invite {
@@ -91,45 +91,72 @@ impl TypeMapKey for InviteStore {
     type Value = RwLock<HashMap<GuildId, HashMap<String, Invite>>>;
 }
 
+/// Guilds whose invites couldn't be loaded, e.g. because the bot isn't allowed
+/// to see them
+#[derive(Debug)]
+pub struct FailedInviteLoads;
+
+impl TypeMapKey for FailedInviteLoads {
+    type Value = HashSet<GuildId>;
+}
+
 // FIXME: listen for permission update in case the bot didnt have the permission
 // to see invites but now has
 impl InviteStore {
     #[instrument(skip_all, name = "add_invites_created_guild", level = "debug")]
     pub async fn invite_guild_created(ctx: Context, guild: &Guild) {
-        match guild.invites(ctx.http()).await {
-            Ok(invites) => {
-                event!(
-                    Level::DEBUG,
-                    guild = guild.id.0,
-                    invites = invites.len(),
-                    "loaded {} invite(s) for guild {}",
-                    invites.len(),
-                    guild.id.0
-                );
-                let invites = invites
-                    .into_iter()
-                    .map(|i| (i.code.clone(), Invite::from(i)));
-                let mut writer = ctx.data.write().await;
+        if let Err(e) = Self::load(&ctx, guild.id).await {
+            event!(Level::WARN, error = ?e, "failed to load invites for guild {}: {}", guild.id.0, e);
+        }
+    }
 
-                // this can be thought of as the get_or_insert
-                match writer.get::<InviteStore>() {
-                    Some(store) => {
-                        store.write().await.insert(guild.id, invites.collect());
-                    }
-                    None => {
-                        // "inital" store with the invites that we loaded for this guild
-                        // This branch should be called exactly once for every guild the bot
-                        // joined
-                        let store: HashMap<GuildId, HashMap<String, Invite>> =
-                            HashMap::from_iter(once((guild.id, HashMap::from_iter(invites))));
-                        writer.insert::<InviteStore>(RwLock::new(store));
-                    }
-                };
-            }
+    /// Replace the stored invites of `guild` with the current ones and return
+    /// how many there are
+    pub async fn load(ctx: &Context, guild: GuildId) -> serenity::Result<usize> {
+        let invites = match guild.invites(ctx.http()).await {
+            Ok(invites) => invites,
             Err(e) => {
-                event!(Level::WARN, error = ?e, "failed to load invites for guild {}: {}", guild.id.0, e);
+                ctx.data
+                    .write()
+                    .await
+                    .entry::<FailedInviteLoads>()
+                    .or_default()
+                    .insert(guild);
+                return Err(e);
             }
+        };
+        event!(
+            Level::DEBUG,
+            guild = guild.0,
+            invites = invites.len(),
+            "loaded {} invite(s) for guild {}",
+            invites.len(),
+            guild.0
+        );
+        let count = invites.len();
+        let invites = invites
+            .into_iter()
+            .map(|i| (i.code.clone(), Invite::from(i)));
+        let mut writer = ctx.data.write().await;
+        if let Some(failed) = writer.get_mut::<FailedInviteLoads>() {
+            failed.remove(&guild);
         }
+
+        // this can be thought of as the get_or_insert
+        match writer.get::<InviteStore>() {
+            Some(store) => {
+                store.write().await.insert(guild, invites.collect());
+            }
+            None => {
+                // "inital" store with the invites that we loaded for this guild
+                // This branch should be called exactly once for every guild the bot
+                // joined
+                let store: HashMap<GuildId, HashMap<String, Invite>> =
+                    HashMap::from_iter(once((guild, HashMap::from_iter(invites))));
+                writer.insert::<InviteStore>(RwLock::new(store));
+            }
+        };
+        Ok(count)
     }
 
     #[instrument(skip_all, name = "remove_invites_deleted_guild")]
@@ -139,10 +166,11 @@ impl InviteStore {
             "Guild deleted, deleting all invites from guild {}",
             guild.id.0
         );
-        ctx.data
-            .read()
-            .await
-            .get::<InviteStore>()
+        let mut data = ctx.data.write().await;
+        if let Some(failed) = data.get_mut::<FailedInviteLoads>() {
+            failed.remove(&guild.id);
+        }
+        data.get::<InviteStore>()
             .unwrap()
             .write()
             .await
@@ -196,11 +224,23 @@ impl InviteStore {
     }
 }
 
+/// The invite a member joined with
+#[derive(Debug, Clone)]
+pub struct Attribution {
+    pub code: String,
+    pub invite: Invite,
+}
+
 pub struct InviteTracker;
 
 impl InviteTracker {
+    /// Find out which invite `member` used and store it
+    ///
+    /// Members that can't be attributed to an invite are kicked or
+    /// quarantined, depending on the settings of the guild, and [`None`] is
+    /// returned.
     #[instrument(skip_all, name = "guild_member_add", level = "debug")]
-    pub async fn on_join(ctx: Context, member: Member) {
+    pub async fn on_join(ctx: &Context, member: &Member) -> Option<Attribution> {
         event!(
             Level::INFO,
             member = member.user.id.0,
@@ -218,7 +258,7 @@ impl InviteTracker {
                 "member {} is a bot, invite not tracked",
                 member.user.id.0
             );
-            return;
+            return None;
         }
 
         // this event is kinda hacky, because it assumes that 1) the join event
@@ -232,6 +272,7 @@ impl InviteTracker {
             .instrument(info_span!("read_invites_wait"))
             .await;
         let store = reader.get::<InviteStore>().unwrap();
+        let data = reader.get::<Data>().unwrap();
 
         // wrtier only used at the end to update the local cache
         let mut store_reader = store.write().await;
@@ -256,19 +297,14 @@ impl InviteTracker {
             }
             Err(e) => {
                 event!(Level::WARN, error = ?e, "cannot fetch invites for comparison: {}", e);
-                match member
-                    .kick_with_reason(
-                        ctx.http(),
-                        &format!("Cannot fetch invites for comparison: {}", e),
-                    )
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(e) => {
-                        event!(Level::WARN, member = member.user.id.0, error = ?e, "cannot kick member {}: {}", member.user.id.0, e)
-                    }
-                }
-                return;
+                Self::remove(
+                    ctx,
+                    data,
+                    member,
+                    &format!("Cannot fetch invites for comparison: {}", e),
+                )
+                .await;
+                return None;
             }
         };
 
@@ -277,7 +313,7 @@ impl InviteTracker {
 
         // if were is one invite missing, we know it is a invite which had only one use
         // left
-        let (member, invite, code) = if current_state.len() == (old_state.len() - 1) {
+        let (invite, code, outcome) = if current_state.len() == (old_state.len() - 1) {
             // in this case, we just need to find the invite that is in
             // old_state but not in current_state
             debug_assert!(old_state.is_superset(&current_state));
@@ -295,7 +331,7 @@ impl InviteTracker {
             );
             // This will be handled by the invite delete event
             // old_state_store.remove(&code);
-            (member, invite, *invite_code)
+            (invite, *invite_code, "vanished")
         } else {
             // all element in old_state are still present in current_state BUT their
             // metadata (which is only stored in the two *_store variants) is different.
@@ -312,7 +348,7 @@ impl InviteTracker {
                     // update _this_ invite in the local invite cache. This is needed, because the
                     // `use` count has changed, because this invite was used.
                     old_state_store.insert(code.to_owned(), new_invite.to_owned());
-                    (member, new_invite, code)
+                    (new_invite, code, "matched")
                 }
                 None => {
                     event!(
@@ -323,69 +359,121 @@ impl InviteTracker {
                         member.user.id.0,
                         member.guild_id.0
                     );
-                    match member
-                        .kick_with_reason(
-                            ctx.http(),
-                            "failed to associate an invite with this member",
-                        )
-                        .await
-                    {
-                        Ok(_) => (),
-                        Err(e) => {
-                            event!(Level::WARN, member = member.user.id.0, error = ?e, "cannot kick member {}: {}", member.user.id.0, e)
-                        }
-                    }
-                    return;
+                    Self::remove(
+                        ctx,
+                        data,
+                        member,
+                        "failed to associate an invite with this member",
+                    )
+                    .await;
+                    return None;
                 }
             }
         };
 
-        let data = reader.get::<Data>().unwrap();
         match sqlx::query!(
             r#"
-        INSERT INTO invited_members ("user", inviter, invite, guild)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO invited_members ("user", inviter, invite, guild, confirmed)
+        VALUES ($1, $2, $3, $4, $5)
         ON CONFLICT("user", "guild") DO UPDATE
         SET inviter = EXCLUDED.inviter,
         invite = EXCLUDED.invite,
-        used_at = EXCLUDED.used_at
+        used_at = EXCLUDED.used_at,
+        confirmed = EXCLUDED.confirmed
         "#,
             member.user.id.0.to_string(),
             invite.inviter.0.to_string(),
             code,
             invite.guild.0.to_string(),
+            // members of guilds with verification are confirmed once they
+            // verified
+            data.guild(member.guild_id).verification.is_none(),
         )
         .execute(&data.pool)
         .await
         {
-            Ok(_) => event!(
-                Level::INFO,
-                "{} is the inviter of {} on guild {}",
-                invite.inviter.0,
-                member.user.id.0,
-                invite.guild.0
-            ),
+            Ok(_) => {
+                event!(
+                    Level::INFO,
+                    "{} is the inviter of {} on guild {}",
+                    invite.inviter.0,
+                    member.user.id.0,
+                    invite.guild.0
+                );
+                METRICS
+                    .invite_attributions
+                    .with_label_values(&[outcome])
+                    .inc();
+            }
             Err(e) => {
                 event!(Level::ERROR, error = ?e, "failed to insert into database: {}", e);
-                match member
-                    .kick_with_reason(ctx.http(), "error inserting user in database")
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(e) => event!(
+                Self::remove(ctx, data, member, "error inserting user in database").await;
+                return None;
+            }
+        }
+
+        event!(Level::DEBUG, "invite_store at end: {:#?}", old_state_store);
+        Some(Attribution {
+            code: code.to_owned(),
+            invite: invite.to_owned(),
+        })
+    }
+
+    /// Kick or quarantine a member whose invite couldn't be tracked
+    ///
+    /// Counts the attribution as `kicked` or `quarantined` if that worked, as
+    /// `deferred` if it waits for the end of maintenance mode and as `failed`
+    /// otherwise, so every join is counted once.
+    async fn remove(ctx: &Context, data: &Data, member: &Member, reason: &str) {
+        let config = data.guild(member.guild_id);
+        // without a quarantine role, the member would keep full access
+        let remedy = config.untracked.available(&config);
+        let outcome = if data.in_maintenance(member.guild_id) {
+            data.maintenance
+                .defer(
+                    &data.pool,
+                    member.guild_id,
+                    member.user.id,
+                    &remedy.to_string(),
+                    reason,
+                )
+                .await;
+            "deferred"
+        } else {
+            match remedy
+                .apply(
+                    ctx,
+                    data,
+                    member.guild_id,
+                    member.user.id,
+                    ctx.cache.current_user_id(),
+                    reason,
+                )
+                .await
+            {
+                Ok(_) => match remedy {
+                    Remedy::Kick => "kicked",
+                    Remedy::Quarantine => "quarantined",
+                },
+                Err(e) => {
+                    event!(
                         Level::WARN,
                         error = ?e,
                         member = member.user.id.0,
                         guild = member.guild_id.0,
-                        "failed to kick member {} from guild {}: {}",
+                        "failed to {} member {} from guild {}: {}",
+                        remedy,
                         member.user.id.0,
                         member.guild_id.0,
                         e
-                    ),
+                    );
+                    "failed"
                 }
             }
-        }
-
-        event!(Level::DEBUG, "invite_store at end: {:#?}", old_state_store);
+        };
+        METRICS
+            .invite_attributions
+            .with_label_values(&[outcome])
+            .inc();
     }
 }
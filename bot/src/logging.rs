@@ -0,0 +1,76 @@
+//! Setup of the tracing subscriber
+
+use tracing::Subscriber;
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{self, MakeWriter},
+    prelude::*,
+    registry::LookupSpan,
+    reload, EnvFilter, Layer, Registry,
+};
+
+use crate::config::{LogFormat, LogRotation, Tracing};
+
+/// Swaps the filter of the running subscriber
+pub type LogHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Build the filter described by `config`
+pub fn filter(config: &Tracing) -> anyhow::Result<EnvFilter> {
+    EnvFilter::builder()
+        .with_default_directive(LevelFilter::from_level(config.level).into())
+        .parse(config.filter.as_deref().unwrap_or_default())
+        .map_err(|e| anyhow!("Invalid `tracing.filter`: {}", e))
+}
+
+/// Install the global subscriber
+///
+/// The returned guard flushes the log file when it is dropped, so it has to
+/// be kept until the bot exits.
+pub fn init(config: &Tracing) -> anyhow::Result<(LogHandle, Option<WorkerGuard>)> {
+    let (filter, handle) = reload::Layer::new(filter(config)?);
+
+    let mut layers = vec![layer(config.format, std::io::stdout, true)];
+    let guard = match &config.file {
+        Some(file) => {
+            let rotation = match file.rotation {
+                LogRotation::Minutely => Rotation::MINUTELY,
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            };
+            let appender = RollingFileAppender::builder()
+                .rotation(rotation)
+                .filename_prefix(&file.prefix)
+                .build(&file.directory)
+                .map_err(|e| {
+                    anyhow!("Cannot write logs to `{}`: {}", file.directory.display(), e)
+                })?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            layers.push(layer(config.format, writer, false));
+            Some(guard)
+        }
+        None => None,
+    };
+
+    let subscriber = tracing_subscriber::registry().with(filter).with(layers);
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok((handle, guard))
+}
+
+fn layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
@@ -1,29 +1,49 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use figment::{
-    providers::{Env, Format, Toml},
-    Figment,
-};
+use config::ShardMode;
 use handler::GlobalEventHandler;
-use poise::{serenity_prelude::GatewayIntents, FrameworkOptions, PrefixFrameworkOptions};
+use http::HttpState;
+use metrics::METRICS;
+use poise::{
+    serenity_prelude::GatewayIntents, FrameworkError, FrameworkOptions, PrefixFrameworkOptions,
+};
 use secrecy::ExposeSecret;
 use serenity::Client;
 use sqlx::postgres::PgPoolOptions;
 use tokio::sync::RwLock;
-use tracing::Instrument;
+use tracing::{Instrument, Level};
 use tracing_log::LogTracer;
-use tracing_subscriber::FmtSubscriber;
 
+mod appeal;
+mod auditlog;
+mod automod;
 mod commands;
 mod config;
 mod data;
 mod handler;
+mod health;
+mod http;
 mod invite;
+mod logging;
+mod maintenance;
+mod messagelog;
+mod metrics;
+mod modlog;
+mod quarantine;
+mod raid;
 mod register;
+mod screening;
+mod settings;
+mod shutdown;
+mod template;
 mod util;
+mod verification;
 
 #[doc(inline)]
-pub use {config::Config, data::Data};
+pub use {
+    config::{Config, ConfigHandle},
+    data::Data,
+};
 
 #[macro_use]
 extern crate tracing;
@@ -36,6 +56,7 @@ extern crate async_trait;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Context<'a> = poise::Context<'a, Data, Error>;
+pub type ApplicationContext<'a> = poise::ApplicationContext<'a, Data, Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[tokio::main]
@@ -43,16 +64,11 @@ async fn main() -> anyhow::Result<()> {
     // Tracing compatibilty layer for crates that use `log`
     LogTracer::init()?;
 
-    let config: Config = Figment::new()
-        .merge(Toml::file("Bot.toml"))
-        .merge(Env::prefixed("PWNHUB_BOT_").map(|k| k.as_str().replace('_', ".").into()))
-        .extract()
-        .map_err(|e| anyhow!("Failed to load configuration: {}", e))?;
+    let config = Config::load()?;
 
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(config.tracing.level)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+    // the filter can be changed by reloading the config. The guard has to live
+    // until the end, since it flushes the log file.
+    let (log_filter, _guard) = logging::init(&config.tracing)?;
 
     let pool = PgPoolOptions::new()
         .connect(config.database.url.expose_secret())
@@ -76,20 +92,46 @@ async fn main() -> anyhow::Result<()> {
     let client = Client::builder(config.discord.token.expose_secret(), intents);
 
     let owners = config.discord.bot_owners.clone();
-    let prefix = config.discord.prefix.clone();
-    let data = Data::new(pool, config);
+    let data = Data::new(pool, ConfigHandle::new(config, Some(log_filter)));
+    data.settings
+        .load(&data.pool)
+        .await
+        .map_err(|e| anyhow!("Failed to load guild settings: {}", e))?;
     let mut handler: GlobalEventHandler<Data, Error> = GlobalEventHandler {
         options: FrameworkOptions {
             prefix_options: PrefixFrameworkOptions {
-                prefix: Some(prefix),
+                // read from the config every time, so it can be reloaded
+                dynamic_prefix: Some(|ctx| {
+                    Box::pin(async move { Ok(Some(ctx.data.config.get().discord.prefix.clone())) })
+                }),
                 ..Default::default()
             },
             owners,
             commands: vec![
                 register::register(),
                 commands::invite(),
+                commands::admin(),
+                commands::config(),
+                commands::filter(),
                 commands::hackban(),
+                commands::quarantine(),
+                commands::reload_config(),
+                commands::raidmode(),
+                commands::shards(),
+                commands::template(),
             ],
+            command_check: Some(|ctx| Box::pin(shutdown::accepts_commands(ctx))),
+            post_command: |ctx| Box::pin(async move { METRICS.command_finished(ctx, "ok") }),
+            on_error: |error| {
+                Box::pin(async move {
+                    if let FrameworkError::Command { ctx, .. } = &error {
+                        METRICS.command_finished(*ctx, "error");
+                    }
+                    if let Err(e) = poise::builtins::on_error(error).await {
+                        event!(Level::ERROR, error = ?e, "failed to handle an error: {}", e);
+                    }
+                })
+            },
             ..Default::default()
         },
         data: data.clone(),
@@ -98,6 +140,7 @@ async fn main() -> anyhow::Result<()> {
         shard_manager: RwLock::const_new(None),
         // this is set in the Ready event
         whoami: RwLock::const_new(None),
+        shutdown: data.shutdown.clone(),
     };
 
     poise::set_qualified_names(&mut handler.options.commands);
@@ -111,19 +154,62 @@ async fn main() -> anyhow::Result<()> {
 
     *handler.shard_manager.write().await = Some(client.shard_manager.clone());
 
-    let shardmanager = client.shard_manager.clone();
-
-    tokio::spawn(async move {
-        match tokio::signal::ctrl_c().await {
-            Ok(()) => {
-                let mut x = (*shardmanager).lock().await;
-                x.shutdown_all().await;
+    #[cfg(unix)]
+    {
+        let config = handler.data.config.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    event!(Level::WARN, error = ?e, "cannot listen for SIGHUP, the config can only be reloaded with /reload-config: {}", e);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                if let Err(e) = config.reload() {
+                    event!(Level::ERROR, error = ?e, "the config wasn't reloaded: {}", e);
+                }
             }
-            Err(err) => panic!("Unable to listen for shutdown signal: {}", err),
+        });
+    }
+
+    if let Some(config) = handler.data.config.get().http.clone() {
+        let state = HttpState {
+            handler: handler.clone(),
+            typemap: client.data.clone(),
+            cache: client.cache_and_http.cache.clone(),
+            disconnected: Default::default(),
         };
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(&config, state).await {
+                event!(Level::ERROR, error = ?e, "the http listener stopped: {}", e);
+            }
+        });
+    }
+
+    let shutdown = handler.data.shutdown.clone();
+    let config = handler.data.config.clone();
+    let shardmanager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        shutdown
+            .drain(Duration::from_secs(config.get().shutdown.timeout))
+            .await;
+        shardmanager.lock().await.shutdown_all().await;
     });
 
-    client.start().await?;
+    let result = match handler.data.config.get().discord.shards {
+        ShardMode::Auto => client.start_autosharded().await,
+        ShardMode::Fixed(total) => client.start_shards(total).await,
+        ShardMode::Range { first, last, total } => {
+            client.start_shard_range([first, last], total).await
+        }
+    };
+    handler.data.pool.close().await;
+    event!(Level::INFO, "closed the database pool");
+    result?;
 
     Ok(())
 }
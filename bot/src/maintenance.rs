@@ -0,0 +1,106 @@
+//! Maintenance mode
+//!
+//! While a guild is under maintenance, the bot keeps logging but doesn't
+//! kick, quarantine, time out or ban anyone on its own, and moderation
+//! commands are refused. The actions it would have taken are stored in
+//! `deferred_actions`, so they can be reviewed with `/admin deferred`.
+//!
+//! Maintenance is either on for all guilds, with `/admin maintenance` or
+//! `maintenance` in the config file, or for a single guild, with
+//! `/admin maintenance` and the ID of the guild or `maintenance` in the
+//! settings of the guild. Only the owners can change it, `/config` can't lift
+//! it. Only the toggle for all guilds isn't stored: it resets to off when the
+//! bot restarts, so use the config file to keep the bot under maintenance
+//! across restarts.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use poise::serenity_prelude::{GuildId, UserId};
+use sqlx::PgPool;
+use tracing::Level;
+
+use crate::{Context, Result};
+
+/// Whether the bot is under maintenance in all guilds, toggled by the owners
+/// with `/admin maintenance`. It lives in memory only and is off after a
+/// restart.
+#[derive(Debug, Clone, Default)]
+pub struct Maintenance {
+    global: Arc<AtomicBool>,
+}
+
+impl Maintenance {
+    pub fn is_enabled(&self) -> bool {
+        self.global.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.global.store(enabled, Ordering::Relaxed);
+        event!(
+            Level::INFO,
+            enabled,
+            "maintenance mode is now {}",
+            match enabled {
+                true => "on",
+                false => "off",
+            }
+        );
+    }
+
+    /// Store an action that wasn't taken because of maintenance
+    pub async fn defer(
+        &self,
+        pool: &PgPool,
+        guild: GuildId,
+        user: UserId,
+        action: &str,
+        reason: &str,
+    ) {
+        event!(
+            Level::INFO,
+            member = user.0,
+            guild = guild.0,
+            action,
+            "maintenance: holding back {} of member {} in guild {}: {}",
+            action,
+            user.0,
+            guild.0,
+            reason
+        );
+        if let Err(e) = sqlx::query!(
+            r#"
+        INSERT INTO deferred_actions (guild, "user", action, reason)
+        VALUES ($1, $2, $3, $4)
+        "#,
+            guild.0.to_string(),
+            user.0.to_string(),
+            action,
+            reason,
+        )
+        .execute(pool)
+        .await
+        {
+            event!(Level::ERROR, error = ?e, "failed to store deferred {} of member {}: {}", action, user.0, e);
+        }
+    }
+}
+
+/// Refuse moderation commands in guilds under maintenance
+pub async fn allows_moderation(ctx: Context<'_>) -> Result<bool> {
+    match ctx.guild_id() {
+        Some(guild) if ctx.data().in_maintenance(guild) => {
+            ctx.send(|m| {
+                m.content(
+                    "Maintenance mode is active, moderation actions are paused. Try again later.",
+                )
+                .ephemeral(true)
+            })
+            .await?;
+            Ok(false)
+        }
+        _ => Ok(true),
+    }
+}
@@ -0,0 +1,475 @@
+//! Message log
+//!
+//! Edited and deleted messages are posted to the message log channel of the
+//! guild. Discord only sends the id of a deleted message and serenity's cache
+//! doesn't keep messages for long, so the messages of guilds with a message
+//! log are kept in a bounded cache of their own.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{
+    AttachmentType, CacheHttp, ChannelId, Color, Context, CreateEmbed, GuildId, Message, MessageId,
+    MessageUpdateEvent, UserId,
+};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::Level;
+
+use crate::{util::channel_scopes, Data};
+
+/// How many messages are cached at most, across all guilds
+const MAX_CACHED: usize = 10_000;
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct MessageLogConfig {
+    /// The channel edited and deleted messages are posted to
+    #[serde_as(as = "DisplayFromStr")]
+    pub channel: ChannelId,
+    /// Channels and categories whose messages aren't logged
+    #[serde_as(as = "HashSet<DisplayFromStr>")]
+    #[serde(default)]
+    pub ignored: HashSet<ChannelId>,
+}
+
+/// A message as it was when it was sent or last edited
+#[derive(Debug, Clone)]
+pub struct CachedMessage {
+    pub author: UserId,
+    pub author_tag: String,
+    pub content: String,
+    /// File names and URLs of the attachments
+    pub attachments: Vec<(String, String)>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl From<&Message> for CachedMessage {
+    fn from(message: &Message) -> Self {
+        Self {
+            author: message.author.id,
+            author_tag: message.author.tag(),
+            content: message.content.clone(),
+            attachments: message
+                .attachments
+                .iter()
+                .map(|a| (a.filename.clone(), a.url.clone()))
+                .collect(),
+            timestamp: *message.timestamp,
+        }
+    }
+}
+
+/// The most recent messages, the oldest are evicted first
+#[derive(Debug)]
+pub struct MessageCache {
+    messages: HashMap<MessageId, CachedMessage>,
+    /// Message ids in the order they were inserted
+    order: VecDeque<MessageId>,
+    capacity: usize,
+}
+
+impl Default for MessageCache {
+    fn default() -> Self {
+        Self::with_capacity(MAX_CACHED)
+    }
+}
+
+impl MessageCache {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            messages: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn insert(&mut self, id: MessageId, message: CachedMessage) {
+        if self.messages.insert(id, message).is_none() {
+            self.order.push_back(id);
+            while self.order.len() > self.capacity {
+                if let Some(evicted) = self.order.pop_front() {
+                    self.messages.remove(&evicted);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: MessageId) -> Option<&CachedMessage> {
+        self.messages.get(&id)
+    }
+
+    pub fn remove(&mut self, id: MessageId) -> Option<CachedMessage> {
+        let message = self.messages.remove(&id)?;
+        if let Some(i) = self.order.iter().position(|m| *m == id) {
+            self.order.remove(i);
+        }
+        Some(message)
+    }
+}
+
+pub struct MessageLog;
+
+impl MessageLog {
+    /// The message log settings of `guild`, if messages in `channel` are
+    /// logged
+    fn config(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        channel: ChannelId,
+    ) -> Option<MessageLogConfig> {
+        let config = data.guild(guild).messagelog?;
+        let scopes = channel_scopes(ctx, channel);
+        match scopes.contains(&config.channel) || scopes.iter().any(|s| config.ignored.contains(s))
+        {
+            true => None,
+            false => Some(config),
+        }
+    }
+
+    /// Remember a new message in case it is edited or deleted later
+    pub fn on_message(ctx: &Context, data: &Data, message: &Message) {
+        let guild = match message.guild_id {
+            Some(guild) if !message.author.bot => guild,
+            _ => return,
+        };
+        if Self::config(ctx, data, guild, message.channel_id).is_some() {
+            data.messages
+                .lock()
+                .unwrap()
+                .insert(message.id, message.into());
+        }
+    }
+
+    /// Post the content of a message before and after it was edited
+    #[instrument(skip_all, name = "message_log_update", level = "debug")]
+    pub async fn on_update(
+        ctx: &Context,
+        data: &Data,
+        old: Option<Message>,
+        event: &MessageUpdateEvent,
+    ) {
+        let (guild, content) = match (event.guild_id, &event.content) {
+            (Some(guild), Some(content)) => (guild, content),
+            // embeds being resolved also update the message
+            _ => return,
+        };
+        let config = match Self::config(ctx, data, guild, event.channel_id) {
+            Some(config) => config,
+            None => return,
+        };
+
+        let before = {
+            let mut messages = data.messages.lock().unwrap();
+            let before = messages
+                .get(event.id)
+                .cloned()
+                .or_else(|| old.as_ref().map(Into::into));
+            if let Some(before) = &before {
+                messages.insert(
+                    event.id,
+                    CachedMessage {
+                        content: content.clone(),
+                        ..before.clone()
+                    },
+                );
+            }
+            before
+        };
+        let (author, author_tag) = match (&before, &event.author) {
+            (_, Some(author)) if author.bot => return,
+            (Some(before), _) => (before.author, before.author_tag.clone()),
+            (None, Some(author)) => (author.id, author.tag()),
+            (None, None) => return,
+        };
+        if before.as_ref().map(|b| &b.content) == Some(content) {
+            return;
+        }
+
+        let link = format!(
+            "https://discord.com/channels/{}/{}/{}",
+            guild.0, event.channel_id.0, event.id.0
+        );
+        Self::post(ctx, &config, |e| {
+            e.title("Message edited");
+            e.color(Color::GOLD);
+            e.description(format!(
+                "<@{}> edited a [message]({}) in <#{}>",
+                author, link, event.channel_id
+            ));
+            e.field(
+                "Before",
+                truncate(
+                    before
+                        .as_ref()
+                        .map(|b| b.content.as_str())
+                        .unwrap_or("*Unknown, the message isn't cached*"),
+                    1024,
+                ),
+                false,
+            );
+            e.field("After", truncate(content, 1024), false);
+            e.footer(|f| {
+                f.text(format!(
+                    "{} ({}) • message {}",
+                    author_tag, author, event.id
+                ))
+            });
+            e.timestamp(Utc::now())
+        })
+        .await;
+    }
+
+    /// Post the content of a deleted message
+    #[instrument(skip_all, name = "message_log_delete", level = "debug")]
+    pub async fn on_delete(
+        ctx: &Context,
+        data: &Data,
+        guild: Option<GuildId>,
+        channel: ChannelId,
+        id: MessageId,
+    ) {
+        let message = data.messages.lock().unwrap().remove(id);
+        let (guild, message) = match (guild, message) {
+            (Some(guild), Some(message)) => (guild, message),
+            // messages of bots and messages that were sent before the cache
+            // have nothing to show
+            _ => return,
+        };
+        let config = match Self::config(ctx, data, guild, channel) {
+            Some(config) => config,
+            None => return,
+        };
+
+        Self::post(ctx, &config, |e| {
+            e.title("Message deleted");
+            e.color(Color::RED);
+            e.description(format!(
+                "<@{}> sent a message in <#{}>\n\n{}",
+                message.author,
+                channel,
+                truncate(&message.content, 3800)
+            ));
+            if !message.attachments.is_empty() {
+                e.field(
+                    "Attachments",
+                    truncate(
+                        &message
+                            .attachments
+                            .iter()
+                            .map(|(name, url)| format!("[{}]({})", name, url))
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                        1024,
+                    ),
+                    false,
+                );
+            }
+            e.footer(|f| {
+                f.text(format!(
+                    "{} ({}) • message {}",
+                    message.author_tag, message.author, id
+                ))
+            });
+            e.timestamp(message.timestamp)
+        })
+        .await;
+    }
+
+    /// Post a transcript of messages that were deleted at once, e.g. by a
+    /// purge
+    #[instrument(skip_all, name = "message_log_delete_bulk", level = "debug")]
+    pub async fn on_bulk_delete(
+        ctx: &Context,
+        data: &Data,
+        guild: Option<GuildId>,
+        channel: ChannelId,
+        ids: &[MessageId],
+    ) {
+        let mut messages = {
+            let mut cache = data.messages.lock().unwrap();
+            ids.iter()
+                .filter_map(|id| cache.remove(*id).map(|m| (*id, m)))
+                .collect::<Vec<_>>()
+        };
+        let guild = match guild {
+            Some(guild) => guild,
+            None => return,
+        };
+        let config = match Self::config(ctx, data, guild, channel) {
+            Some(config) => config,
+            None => return,
+        };
+        messages.sort_by_key(|(id, _)| *id);
+
+        let mut transcript = String::new();
+        for (id, message) in &messages {
+            transcript.push_str(&format!(
+                "[{}] {} ({}) • message {}\n",
+                message.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                message.author_tag,
+                message.author,
+                id
+            ));
+            if !message.content.is_empty() {
+                transcript.push_str(&message.content);
+                transcript.push('\n');
+            }
+            for (name, url) in &message.attachments {
+                transcript.push_str(&format!("Attachment {}: {}\n", name, url));
+            }
+            transcript.push('\n');
+        }
+
+        let unknown = ids.len() - messages.len();
+        let result = config
+            .channel
+            .send_message(ctx.http(), |m| {
+                m.embed(|e| {
+                    e.title("Messages deleted");
+                    e.color(Color::DARK_RED);
+                    e.description(format!(
+                        "{} messages were deleted in <#{}>",
+                        ids.len(),
+                        channel
+                    ));
+                    if unknown > 0 {
+                        e.field(
+                            "Not cached",
+                            format!("The content of {} messages is unknown", unknown),
+                            false,
+                        );
+                    }
+                    e.timestamp(Utc::now())
+                });
+                if !messages.is_empty() {
+                    m.add_file(AttachmentType::Bytes {
+                        data: transcript.into_bytes().into(),
+                        filename: format!("deleted-{}.txt", channel),
+                    });
+                }
+                m
+            })
+            .await;
+        if let Err(e) = result {
+            event!(Level::WARN, guild = guild.0, error = ?e, "failed to post to the message log of guild {}: {}", guild.0, e);
+        }
+    }
+
+    async fn post<F>(ctx: &Context, config: &MessageLogConfig, f: F)
+    where
+        F: FnOnce(&mut CreateEmbed) -> &mut CreateEmbed,
+    {
+        if let Err(e) = config
+            .channel
+            .send_message(ctx.http(), |m| {
+                m.embed(f);
+                m.allowed_mentions(|a| a.empty_parse())
+            })
+            .await
+        {
+            event!(Level::WARN, channel = config.channel.0, error = ?e, "failed to post to the message log channel {}: {}", config.channel.0, e);
+        }
+    }
+}
+
+/// Shorten `s` to at most `max` characters
+///
+/// Embed fields can't be empty, so empty strings are replaced.
+fn truncate(s: &str, max: usize) -> String {
+    if s.is_empty() {
+        return "*No content*".to_string();
+    }
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    // one character is left for the ellipsis
+    let end = s.char_indices().nth(max - 1).map_or(s.len(), |(i, _)| i);
+    format!("{}…", &s[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn message(content: &str) -> CachedMessage {
+        CachedMessage {
+            author: UserId(1),
+            author_tag: "user#0001".to_string(),
+            content: content.to_string(),
+            attachments: Vec::new(),
+            timestamp: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn oldest_messages_are_evicted() {
+        let mut cache = MessageCache::with_capacity(2);
+        for id in 1..=3 {
+            cache.insert(MessageId(id), message(&id.to_string()));
+        }
+        assert!(cache.get(MessageId(1)).is_none());
+        assert_eq!(cache.get(MessageId(2)).unwrap().content, "2");
+        assert_eq!(cache.get(MessageId(3)).unwrap().content, "3");
+    }
+
+    #[test]
+    fn updates_keep_their_place() {
+        let mut cache = MessageCache::with_capacity(2);
+        cache.insert(MessageId(1), message("a"));
+        cache.insert(MessageId(2), message("b"));
+        cache.insert(MessageId(1), message("edited"));
+        cache.insert(MessageId(3), message("c"));
+        assert!(cache.get(MessageId(1)).is_none());
+        assert!(cache.get(MessageId(2)).is_some());
+    }
+
+    #[test]
+    fn removed_messages_free_their_place() {
+        let mut cache = MessageCache::with_capacity(2);
+        cache.insert(MessageId(1), message("a"));
+        cache.insert(MessageId(2), message("b"));
+        assert_eq!(cache.remove(MessageId(1)).unwrap().content, "a");
+        assert!(cache.remove(MessageId(1)).is_none());
+        cache.insert(MessageId(3), message("c"));
+        assert!(cache.get(MessageId(2)).is_some());
+        assert!(cache.get(MessageId(3)).is_some());
+    }
+
+    #[test]
+    fn reinserted_messages_are_not_evicted_early() {
+        let mut cache = MessageCache::with_capacity(2);
+        cache.insert(MessageId(1), message("a"));
+        cache.remove(MessageId(1));
+        cache.insert(MessageId(2), message("b"));
+        cache.insert(MessageId(1), message("a again"));
+        assert_eq!(cache.get(MessageId(1)).unwrap().content, "a again");
+        assert!(cache.get(MessageId(2)).is_some());
+        cache.insert(MessageId(3), message("c"));
+        // 2 is the oldest now
+        assert!(cache.get(MessageId(2)).is_none());
+        assert!(cache.get(MessageId(1)).is_some());
+    }
+
+    #[test]
+    fn truncate_long_text() {
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("hello", 5), "hello");
+        assert_eq!(truncate("hello world", 5), "hell…");
+        assert_eq!(truncate("hello world", 5).chars().count(), 5);
+    }
+
+    #[test]
+    fn truncate_multibyte_characters() {
+        assert_eq!(truncate("äöüäöü", 3), "äö…");
+        assert_eq!(truncate("👋👋👋", 3), "👋👋👋");
+    }
+
+    #[test]
+    fn truncate_empty_text() {
+        assert_eq!(truncate("", 10), "*No content*");
+    }
+}
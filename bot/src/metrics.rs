@@ -0,0 +1,143 @@
+//! Prometheus metrics
+//!
+//! Counters are updated where the events happen. Gauges that describe the
+//! current state, like the gateway latency, are updated whenever the metrics
+//! are scraped.
+
+use std::sync::LazyLock;
+
+use chrono::Utc;
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::{http::HttpState, invite::InviteStore, Context};
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// Finished commands by command and outcome (`ok` or `error`)
+    pub commands: IntCounterVec,
+    pub command_duration: HistogramVec,
+    /// Members whose invite was looked up, by outcome: `matched`, `vanished`
+    /// (a single use invite that was used up), `kicked` or `quarantined` for
+    /// members that were removed because of a failure, `deferred` if their
+    /// removal waits for the end of maintenance mode and `failed` if they
+    /// couldn't be removed. Every join is counted once.
+    pub invite_attributions: IntCounterVec,
+    /// Logged cases by action, including the ones that lift a sanction
+    pub cases: IntCounterVec,
+    gateway_latency: GaugeVec,
+    db_connections: IntGauge,
+    db_idle_connections: IntGauge,
+    stored_invites: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("pwnhub".to_string()), None).unwrap();
+        let metrics = Self {
+            commands: IntCounterVec::new(
+                Opts::new("commands_total", "Finished commands"),
+                &["command", "outcome"],
+            )
+            .unwrap(),
+            command_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "command_duration_seconds",
+                    "Time from the invocation to the end of a command",
+                ),
+                &["command"],
+            )
+            .unwrap(),
+            invite_attributions: IntCounterVec::new(
+                Opts::new(
+                    "invite_attributions_total",
+                    "Attempts to find the invite of a new member",
+                ),
+                &["outcome"],
+            )
+            .unwrap(),
+            cases: IntCounterVec::new(
+                Opts::new("cases_total", "Logged moderation cases"),
+                &["action"],
+            )
+            .unwrap(),
+            gateway_latency: GaugeVec::new(
+                Opts::new(
+                    "gateway_latency_seconds",
+                    "Latency of the last heartbeat of a shard",
+                ),
+                &["shard"],
+            )
+            .unwrap(),
+            db_connections: IntGauge::new("db_connections", "Open database connections").unwrap(),
+            db_idle_connections: IntGauge::new("db_idle_connections", "Idle database connections")
+                .unwrap(),
+            stored_invites: IntGaugeVec::new(
+                Opts::new("stored_invites", "Invites in the invite store"),
+                &["guild"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        for collector in [
+            Box::new(metrics.commands.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.command_duration.clone()),
+            Box::new(metrics.invite_attributions.clone()),
+            Box::new(metrics.cases.clone()),
+            Box::new(metrics.gateway_latency.clone()),
+            Box::new(metrics.db_connections.clone()),
+            Box::new(metrics.db_idle_connections.clone()),
+            Box::new(metrics.stored_invites.clone()),
+        ] {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// Record a command that just finished
+    pub fn command_finished(&self, ctx: Context<'_>, outcome: &str) {
+        let command = &ctx.command().qualified_name;
+        self.commands.with_label_values(&[command, outcome]).inc();
+        let duration = Utc::now() - *ctx.created_at();
+        self.command_duration
+            .with_label_values(&[command])
+            .observe(duration.num_milliseconds().max(0) as f64 / 1000.0);
+    }
+
+    /// Update the gauges and encode all metrics in the text format
+    pub async fn gather(&self, state: &HttpState) -> anyhow::Result<String> {
+        let pool = &state.handler.data.pool;
+        self.db_connections.set(pool.size().into());
+        self.db_idle_connections.set(pool.num_idle() as i64);
+
+        self.gateway_latency.reset();
+        if let Some(shard_manager) = state.handler.shard_manager.read().await.as_ref() {
+            let shard_manager = shard_manager.lock().await;
+            for (id, runner) in shard_manager.runners.lock().await.iter() {
+                if let Some(latency) = runner.latency {
+                    self.gateway_latency
+                        .with_label_values(&[&id.0.to_string()])
+                        .set(latency.as_secs_f64());
+                }
+            }
+        }
+
+        self.stored_invites.reset();
+        if let Some(store) = state.typemap.read().await.get::<InviteStore>() {
+            for (guild, invites) in store.read().await.iter() {
+                self.stored_invites
+                    .with_label_values(&[&guild.0.to_string()])
+                    .set(invites.len() as i64);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
@@ -0,0 +1,390 @@
+//! Moderation log
+//!
+//! Every action that is taken against a user is stored as a case in the
+//! database and, if the guild has configured a mod log channel, posted there.
+
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{
+    CacheHttp, ChannelId, Color, Context, CreateEmbed, GuildId, MessageId, UserId,
+};
+use sqlx::PgPool;
+use tracing::Level;
+
+use crate::{
+    metrics::METRICS,
+    util::{format_duration, Delivery},
+    Data,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Warn,
+    Timeout,
+    Ban,
+    Kick,
+    InviteRevoke,
+    RemoveTimeout,
+    Unban,
+    /// A member was brought to the attention of the moderators
+    Flag,
+    /// A member's roles were replaced by the quarantine role
+    Quarantine,
+    /// A quarantined member got their roles back
+    Release,
+}
+
+impl Action {
+    /// The name this action is stored as in the database
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Action::Warn => "warn",
+            Action::Timeout => "timeout",
+            Action::Ban => "ban",
+            Action::Kick => "kick",
+            Action::InviteRevoke => "invite_revoke",
+            Action::RemoveTimeout => "remove_timeout",
+            Action::Unban => "unban",
+            Action::Flag => "flag",
+            Action::Quarantine => "quarantine",
+            Action::Release => "release",
+        }
+    }
+
+    const fn color(&self) -> Color {
+        match self {
+            Action::Warn => Color::GOLD,
+            Action::Timeout => Color::ORANGE,
+            Action::Ban => Color::RED,
+            Action::Kick => Color::DARK_ORANGE,
+            Action::InviteRevoke => Color::DARK_GOLD,
+            Action::RemoveTimeout | Action::Unban | Action::Release => Color::DARK_GREEN,
+            Action::Flag => Color::BLUE,
+            Action::Quarantine => Color::DARK_PURPLE,
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Warn => "Warning",
+            Action::Timeout => "Timeout",
+            Action::Ban => "Ban",
+            Action::Kick => "Kick",
+            Action::InviteRevoke => "Invite revoked",
+            Action::RemoveTimeout => "Timeout removed",
+            Action::Unban => "Unban",
+            Action::Flag => "Flagged",
+            Action::Quarantine => "Quarantine",
+            Action::Release => "Released from quarantine",
+        })
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Action::Warn),
+            "timeout" => Ok(Action::Timeout),
+            "ban" => Ok(Action::Ban),
+            "kick" => Ok(Action::Kick),
+            "invite_revoke" => Ok(Action::InviteRevoke),
+            "remove_timeout" => Ok(Action::RemoveTimeout),
+            "unban" => Ok(Action::Unban),
+            "flag" => Ok(Action::Flag),
+            "quarantine" => Ok(Action::Quarantine),
+            "release" => Ok(Action::Release),
+            _ => Err(anyhow!("unknown action `{}`", s)),
+        }
+    }
+}
+
+/// An action that should be logged
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub guild: GuildId,
+    pub action: Action,
+    /// The user the action was taken against
+    pub target: UserId,
+    /// The user who took the action
+    pub moderator: UserId,
+    pub reason: Option<String>,
+    /// When the penalty ends. [`None`] for permanent or instant actions.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    pub fn new(guild: GuildId, action: Action, target: UserId, moderator: UserId) -> Self {
+        Self {
+            guild,
+            action,
+            target,
+            moderator,
+            reason: None,
+            until: None,
+        }
+    }
+
+    pub fn reason(mut self, reason: impl ToString) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+}
+
+/// A logged action
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub id: i64,
+    pub entry: Entry,
+    /// The mod log message of this case, if it was posted
+    pub message: Option<(ChannelId, MessageId)>,
+}
+
+impl Case {
+    /// Load a case from the database
+    pub async fn fetch(pool: &PgPool, id: i64) -> anyhow::Result<Option<Self>> {
+        let row = match sqlx::query!(
+            r#"
+        SELECT guild, action, target, moderator, reason, expires_at, log_channel, log_message
+        FROM cases WHERE id = $1
+        "#,
+            id
+        )
+        .fetch_optional(pool)
+        .await?
+        {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        Ok(Some(Self {
+            id,
+            entry: Entry {
+                guild: row.guild.parse()?,
+                action: row.action.parse()?,
+                target: row.target.parse()?,
+                moderator: row.moderator.parse()?,
+                reason: row.reason,
+                until: row.expires_at,
+            },
+            message: match (row.log_channel, row.log_message) {
+                (Some(channel), Some(message)) => Some((channel.parse()?, message.parse()?)),
+                _ => None,
+            },
+        }))
+    }
+
+    /// Link to the mod log message of this case
+    pub fn link(&self) -> Option<String> {
+        self.message.map(|(channel, message)| {
+            format!(
+                "https://discord.com/channels/{}/{}/{}",
+                self.entry.guild.0, channel.0, message.0
+            )
+        })
+    }
+}
+
+pub struct ModLog;
+
+impl ModLog {
+    /// Store `entry` as a new case and post it to the mod log channel of the
+    /// guild
+    ///
+    /// Failures are only logged, since the action itself has already been
+    /// taken at this point. [`None`] is returned if the case couldn't be
+    /// stored.
+    #[instrument(skip_all, name = "mod_log", level = "debug")]
+    pub async fn log(ctx: &Context, data: &Data, entry: Entry) -> Option<Case> {
+        Self::store(ctx, data, None, entry).await
+    }
+
+    /// Take the next case number without storing a case, for a notification
+    /// that has to be sent before the action is taken
+    pub async fn reserve(data: &Data) -> Option<i64> {
+        match sqlx::query_scalar!(
+            r#"SELECT nextval(pg_get_serial_sequence('cases', 'id')) AS "id!""#
+        )
+        .fetch_one(&data.pool)
+        .await
+        {
+            Ok(id) => Some(id),
+            Err(e) => {
+                event!(Level::ERROR, error = ?e, "failed to reserve a case: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Like [`ModLog::log`], but the case gets the number `id` that was
+    /// taken with [`ModLog::reserve`]
+    #[instrument(skip_all, name = "mod_log", level = "debug")]
+    pub async fn log_reserved(ctx: &Context, data: &Data, id: i64, entry: Entry) -> Option<Case> {
+        Self::store(ctx, data, Some(id), entry).await
+    }
+
+    async fn store(ctx: &Context, data: &Data, id: Option<i64>, entry: Entry) -> Option<Case> {
+        METRICS
+            .cases
+            .with_label_values(&[entry.action.as_str()])
+            .inc();
+        let id = match sqlx::query_scalar!(
+            r#"
+        INSERT INTO cases (id, guild, action, target, moderator, reason, expires_at)
+        VALUES (COALESCE($7, nextval(pg_get_serial_sequence('cases', 'id'))), $1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+            entry.guild.0.to_string(),
+            entry.action.as_str(),
+            entry.target.0.to_string(),
+            entry.moderator.0.to_string(),
+            entry.reason,
+            entry.until,
+            id,
+        )
+        .fetch_one(&data.pool)
+        .await
+        {
+            Ok(id) => id,
+            Err(e) => {
+                event!(Level::ERROR, error = ?e, "failed to store case: {}", e);
+                return None;
+            }
+        };
+        event!(
+            Level::INFO,
+            case = id,
+            action = entry.action.as_str(),
+            target = entry.target.0,
+            moderator = entry.moderator.0,
+            guild = entry.guild.0,
+            "case {}: {} against {} by {}",
+            id,
+            entry.action,
+            entry.target.0,
+            entry.moderator.0
+        );
+
+        let channel = match data.guild(entry.guild).modlog {
+            Some(channel) => channel,
+            None => {
+                return Some(Case {
+                    id,
+                    entry,
+                    message: None,
+                })
+            }
+        };
+        let mut case = Case {
+            id,
+            entry,
+            message: None,
+        };
+        let entry = &case.entry;
+
+        match channel
+            .send_message(ctx.http(), |m| {
+                m.embed(|e| {
+                    e.title(format!("Case #{} | {}", id, entry.action));
+                    e.color(entry.action.color());
+                    e.field(
+                        "Target",
+                        format!("<@{}> ({})", entry.target, entry.target),
+                        true,
+                    );
+                    e.field("Moderator", format!("<@{}>", entry.moderator), true);
+                    if let Some(until) = entry.until {
+                        e.field(
+                            "Duration",
+                            format!(
+                                "{} (until <t:{}:f>)",
+                                format_duration(until - Utc::now()),
+                                until.timestamp()
+                            ),
+                            true,
+                        );
+                    } else if entry.action == Action::Ban {
+                        e.field("Duration", "permanent", true);
+                    }
+                    e.field(
+                        "Reason",
+                        entry.reason.as_deref().unwrap_or("No reason given"),
+                        false,
+                    );
+                    e.timestamp(Utc::now());
+                    e
+                })
+            })
+            .await
+        {
+            Ok(message) => {
+                case.message = Some((channel, message.id));
+                if let Err(e) = sqlx::query!(
+                    r#"UPDATE cases SET log_channel = $1, log_message = $2 WHERE id = $3"#,
+                    channel.0.to_string(),
+                    message.id.0.to_string(),
+                    id,
+                )
+                .execute(&data.pool)
+                .await
+                {
+                    event!(Level::WARN, case = id, error = ?e, "failed to store mod log message of case {}: {}", id, e);
+                }
+            }
+            Err(e) => {
+                event!(Level::WARN, case = id, channel = channel.0, error = ?e, "failed to post case {} to the mod log: {}", id, e);
+            }
+        }
+
+        Some(case)
+    }
+
+    /// Record how the user was notified about `case` and add it to the mod
+    /// log message
+    #[instrument(skip_all, name = "mod_log_delivery", level = "debug")]
+    pub async fn record_delivery(ctx: &Context, data: &Data, case: &Case, delivery: Delivery) {
+        if let Err(e) = sqlx::query!(
+            r#"UPDATE cases SET notification = $1 WHERE id = $2"#,
+            delivery.as_str(),
+            case.id,
+        )
+        .execute(&data.pool)
+        .await
+        {
+            event!(Level::WARN, case = case.id, error = ?e, "failed to store notification of case {}: {}", case.id, e);
+        }
+
+        let (channel, message) = match case.message {
+            Some(message) => message,
+            None => return,
+        };
+        let result = async {
+            let message = channel.message(ctx.http(), message).await?;
+            let mut embed = message
+                .embeds
+                .first()
+                .cloned()
+                .map(CreateEmbed::from)
+                .unwrap_or_default();
+            embed.field("Notification", delivery, true);
+            channel
+                .edit_message(ctx.http(), message.id, |m| m.set_embed(embed))
+                .await
+        }
+        .await;
+        if let Err(e) = result {
+            event!(Level::WARN, case = case.id, error = ?e, "failed to add notification to mod log message of case {}: {}", case.id, e);
+        }
+    }
+}
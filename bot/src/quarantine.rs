@@ -0,0 +1,292 @@
+//! Quarantine
+//!
+//! Instead of being kicked, a member can be quarantined: all their roles are
+//! replaced by the quarantine role, which should only be able to see a single
+//! channel. The roles they had are stored in the database and are given back
+//! once the member is released with `/quarantine release`.
+
+use poise::serenity_prelude::{CacheHttp, Context, GuildId, Member, RoleId, UserId};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::Level;
+
+use crate::{
+    config::GuildConfig,
+    modlog::{Action, Case, Entry, ModLog},
+    Data, Result,
+};
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct QuarantineConfig {
+    /// The role quarantined members get instead of their roles
+    #[serde_as(as = "DisplayFromStr")]
+    pub role: RoleId,
+}
+
+/// How a member is removed from the community
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default, poise::ChoiceParameter)]
+#[serde(rename_all = "lowercase")]
+pub enum Remedy {
+    #[default]
+    #[name = "kick"]
+    Kick,
+    /// Requires a configured quarantine role
+    #[name = "quarantine"]
+    Quarantine,
+}
+
+impl Remedy {
+    /// This remedy, or [`Remedy::Kick`] if it is [`Remedy::Quarantine`] and
+    /// `config` has no quarantine role
+    pub fn available(self, config: &GuildConfig) -> Self {
+        match self {
+            Remedy::Quarantine if config.quarantine.is_none() => Remedy::Kick,
+            remedy => remedy,
+        }
+    }
+
+    /// Kick or quarantine `user` and log it to the mod log
+    pub async fn apply(
+        self,
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        user: UserId,
+        moderator: UserId,
+        reason: &str,
+    ) -> Result<Option<Case>> {
+        match self {
+            Remedy::Kick => {
+                guild.kick_with_reason(ctx.http(), user, reason).await?;
+                Ok(ModLog::log(
+                    ctx,
+                    data,
+                    Entry::new(guild, Action::Kick, user, moderator).reason(reason),
+                )
+                .await)
+            }
+            Remedy::Quarantine => {
+                Quarantine::apply(ctx, data, guild, user, moderator, reason).await
+            }
+        }
+    }
+}
+
+pub struct Quarantine;
+
+impl Quarantine {
+    /// Replace the roles of `user` with the quarantine role
+    ///
+    /// Managed roles, e.g. the role of a bot or the booster role, can't be
+    /// removed and are kept. Quarantining a member that is already
+    /// quarantined adds their current roles to the saved ones.
+    #[instrument(skip_all, name = "quarantine", level = "debug")]
+    pub async fn apply(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        user: UserId,
+        moderator: UserId,
+        reason: &str,
+    ) -> Result<Option<Case>> {
+        let config = data
+            .guild(guild)
+            .quarantine
+            .ok_or_else(|| anyhow!("No quarantine role is configured for this server"))?;
+        let member = guild.member(ctx, user).await?;
+        let (kept, removed) = Self::partition(ctx, guild, &member, config.role);
+
+        // the roles are saved before they're removed, and the saved roles are
+        // rolled back if they can't be removed
+        let previous = sqlx::query_scalar!(
+            r#"SELECT roles FROM quarantined_members WHERE guild = $1 AND "user" = $2"#,
+            guild.0.to_string(),
+            user.0.to_string(),
+        )
+        .fetch_optional(&data.pool)
+        .await?;
+        sqlx::query!(
+            r#"
+        INSERT INTO quarantined_members (guild, "user", roles, reason, moderator)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (guild, "user") DO UPDATE
+        SET roles = ARRAY(SELECT DISTINCT unnest(quarantined_members.roles || EXCLUDED.roles)),
+        reason = EXCLUDED.reason,
+        moderator = EXCLUDED.moderator
+        "#,
+            guild.0.to_string(),
+            user.0.to_string(),
+            &removed.iter().map(|r| r.0.to_string()).collect::<Vec<_>>(),
+            reason,
+            moderator.0.to_string(),
+        )
+        .execute(&data.pool)
+        .await?;
+
+        let roles = kept.into_iter().chain([config.role]).collect::<Vec<_>>();
+        if let Err(e) = guild
+            .edit_member(ctx.http(), user, |m| m.roles(roles))
+            .await
+        {
+            Self::rollback(data, guild, user, previous).await;
+            return Err(e.into());
+        }
+        event!(
+            Level::INFO,
+            member = user.0,
+            guild = guild.0,
+            "quarantined member {} on guild {}, removed {} roles",
+            user.0,
+            guild.0,
+            removed.len()
+        );
+
+        Ok(ModLog::log(
+            ctx,
+            data,
+            Entry::new(guild, Action::Quarantine, user, moderator).reason(reason),
+        )
+        .await)
+    }
+
+    /// Restore the saved roles of `user` to `previous` after their roles
+    /// couldn't be replaced
+    async fn rollback(data: &Data, guild: GuildId, user: UserId, previous: Option<Vec<String>>) {
+        let result =
+            match previous {
+                Some(roles) => sqlx::query!(
+                    r#"UPDATE quarantined_members SET roles = $3 WHERE guild = $1 AND "user" = $2"#,
+                    guild.0.to_string(),
+                    user.0.to_string(),
+                    &roles,
+                )
+                .execute(&data.pool)
+                .await,
+                None => {
+                    sqlx::query!(
+                        r#"DELETE FROM quarantined_members WHERE guild = $1 AND "user" = $2"#,
+                        guild.0.to_string(),
+                        user.0.to_string(),
+                    )
+                    .execute(&data.pool)
+                    .await
+                }
+            };
+        if let Err(e) = result {
+            event!(Level::ERROR, member = user.0, guild = guild.0, error = ?e, "failed to roll back the saved roles of member {}: {}", user.0, e);
+        }
+    }
+
+    /// Give a quarantined member their roles back
+    ///
+    /// Saved roles that have been deleted in the meantime are skipped.
+    #[instrument(skip_all, name = "quarantine_release", level = "debug")]
+    pub async fn release(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        user: UserId,
+        moderator: UserId,
+        reason: Option<&str>,
+    ) -> Result<Option<Case>> {
+        let config = data
+            .guild(guild)
+            .quarantine
+            .ok_or_else(|| anyhow!("No quarantine role is configured for this server"))?;
+        let member = guild.member(ctx, user).await?;
+        let saved = sqlx::query_scalar!(
+            r#"SELECT roles FROM quarantined_members WHERE guild = $1 AND "user" = $2"#,
+            guild.0.to_string(),
+            user.0.to_string(),
+        )
+        .fetch_optional(&data.pool)
+        .await?
+        .ok_or_else(|| anyhow!("<@{}> isn't quarantined", user))?;
+
+        let existing = ctx.cache.guild_roles(guild).unwrap_or_default();
+        let roles = member
+            .roles
+            .iter()
+            .copied()
+            .filter(|r| *r != config.role)
+            .chain(
+                saved
+                    .iter()
+                    .filter_map(|r| r.parse().ok().map(RoleId))
+                    .filter(|r| existing.contains_key(r)),
+            )
+            .collect::<Vec<_>>();
+        guild
+            .edit_member(ctx.http(), user, |m| m.roles(roles))
+            .await?;
+
+        sqlx::query!(
+            r#"DELETE FROM quarantined_members WHERE guild = $1 AND "user" = $2"#,
+            guild.0.to_string(),
+            user.0.to_string(),
+        )
+        .execute(&data.pool)
+        .await?;
+        event!(
+            Level::INFO,
+            member = user.0,
+            guild = guild.0,
+            "released member {} on guild {} from quarantine",
+            user.0,
+            guild.0
+        );
+
+        let mut entry = Entry::new(guild, Action::Release, user, moderator);
+        entry.reason = reason.map(ToString::to_string);
+        Ok(ModLog::log(ctx, data, entry).await)
+    }
+
+    /// Quarantine a member again if they left the guild while quarantined
+    #[instrument(skip_all, name = "quarantine_join", level = "debug")]
+    pub async fn on_join(ctx: &Context, data: &Data, member: &Member) {
+        let guild = member.guild_id;
+        let config = match data.guild(guild).quarantine {
+            Some(config) => config,
+            None => return,
+        };
+        let quarantined = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM quarantined_members WHERE guild = $1 AND "user" = $2) AS "exists!""#,
+            guild.0.to_string(),
+            member.user.id.0.to_string(),
+        )
+        .fetch_one(&data.pool)
+        .await;
+        match quarantined {
+            Ok(true) => {
+                if let Err(e) = guild
+                    .edit_member(ctx.http(), member.user.id, |m| m.roles([config.role]))
+                    .await
+                {
+                    event!(Level::WARN, member = member.user.id.0, guild = guild.0, error = ?e, "failed to quarantine member {} again: {}", member.user.id.0, e);
+                }
+            }
+            Ok(false) => {}
+            Err(e) => {
+                event!(Level::WARN, member = member.user.id.0, error = ?e, "failed to check whether member {} is quarantined: {}", member.user.id.0, e)
+            }
+        }
+    }
+
+    /// Split the roles of `member` into the ones that have to be kept and the
+    /// ones that are removed by the quarantine
+    fn partition(
+        ctx: &Context,
+        guild: GuildId,
+        member: &Member,
+        quarantine: RoleId,
+    ) -> (Vec<RoleId>, Vec<RoleId>) {
+        let roles = ctx.cache.guild_roles(guild).unwrap_or_default();
+        member
+            .roles
+            .iter()
+            .copied()
+            .filter(|r| *r != quarantine)
+            .partition(|r| roles.get(r).map(|r| r.managed).unwrap_or(false))
+    }
+}
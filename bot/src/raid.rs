@@ -0,0 +1,966 @@
+//! Raid detection
+//!
+//! Every join is fed into a per-guild sliding window. If too many members
+//! join at once, or too many join through a single invite, the guild enters
+//! raid mode: the verification level can be raised and every member who joins
+//! is quarantined or kicked until no one joined for the cooldown, or a
+//! moderator ends raid mode. If a single invite was abused, the invites of
+//! the guild are paused until raid mode ends. Nothing is deleted, so every
+//! invite keeps its code and its inviter.
+//!
+//! Ongoing raids are stored in the database, so raid mode is resumed and still
+//! restores the guild if the bot restarts in the meantime.
+
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+use poise::serenity_prelude::{
+    CacheHttp, ChannelId, Color, Context, CreateEmbed, GuildId, Result, RoleId, UserId,
+    VerificationLevel,
+};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::Level;
+
+use crate::{
+    invite::Attribution,
+    modlog::{Action, Entry, ModLog},
+    quarantine::Quarantine,
+    util::format_duration,
+    Data,
+};
+
+/// What happens to members who join during a raid
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RaidAction {
+    /// Nothing, moderators are only alerted
+    None,
+    /// The member gets the quarantine role, or is timed out if the guild
+    /// hasn't configured one
+    #[default]
+    Quarantine,
+    Kick,
+}
+
+/// The verification level raid mode raises the guild to
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verification {
+    Low,
+    Medium,
+    High,
+    Highest,
+}
+
+impl From<Verification> for VerificationLevel {
+    fn from(v: Verification) -> Self {
+        match v {
+            Verification::Low => VerificationLevel::Low,
+            Verification::Medium => VerificationLevel::Medium,
+            Verification::High => VerificationLevel::High,
+            Verification::Highest => VerificationLevel::Higher,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct RaidConfig {
+    /// More than this many joins within `interval` start raid mode
+    #[serde(default = "default_joins")]
+    pub joins: usize,
+    /// In seconds
+    #[serde(default = "default_interval")]
+    pub interval: i64,
+    /// More than this many joins through a single invite within `interval`
+    /// start raid mode
+    #[serde(default = "default_invite_joins", rename = "invitejoins")]
+    pub invite_joins: usize,
+    /// Raid mode ends once no one joined for this many seconds
+    #[serde(default = "default_cooldown")]
+    pub cooldown: i64,
+    #[serde(default)]
+    pub action: RaidAction,
+    /// Length of the timeout in seconds if the guild has no quarantine role
+    #[serde(default = "default_quarantine")]
+    pub quarantine: i64,
+    /// The verification level during raid mode. It is restored afterwards.
+    #[serde(default)]
+    pub verification: Option<Verification>,
+    /// The channel moderators are alerted in, the mod log if not set
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub alert: Option<ChannelId>,
+    /// The role that is mentioned in alerts
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, rename = "alertrole")]
+    pub alert_role: Option<RoleId>,
+}
+
+impl Default for RaidConfig {
+    fn default() -> Self {
+        Self {
+            joins: default_joins(),
+            interval: default_interval(),
+            invite_joins: default_invite_joins(),
+            cooldown: default_cooldown(),
+            action: RaidAction::default(),
+            quarantine: default_quarantine(),
+            verification: None,
+            alert: None,
+            alert_role: None,
+        }
+    }
+}
+
+fn default_joins() -> usize {
+    10
+}
+
+fn default_interval() -> i64 {
+    10
+}
+
+fn default_invite_joins() -> usize {
+    5
+}
+
+fn default_cooldown() -> i64 {
+    10 * 60
+}
+
+fn default_quarantine() -> i64 {
+    60 * 60
+}
+
+/// The guild feature that pauses every invite of a guild
+const INVITES_DISABLED: &str = "INVITES_DISABLED";
+
+#[derive(Debug, Clone)]
+struct Join {
+    at: DateTime<Utc>,
+    user: UserId,
+    invite: Option<String>,
+}
+
+/// An ongoing raid
+#[derive(Debug, Clone)]
+pub struct Raid {
+    pub started: DateTime<Utc>,
+    /// The last join during the raid
+    pub last_join: DateTime<Utc>,
+    pub reason: String,
+    /// How many members joined during the raid
+    pub joins: usize,
+    /// The verification level before the raid, if it was raised
+    previous_verification: Option<VerificationLevel>,
+    /// Whether the invites of the guild are paused by the raid
+    paused_invites: bool,
+}
+
+/// What a join means for its guild
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Normal,
+    /// The join started a raid
+    Started {
+        reason: String,
+        /// The members who joined within the interval, including this one
+        members: Vec<UserId>,
+        /// The invite that was abused, if the raid came through a single one
+        invite: Option<String>,
+    },
+    /// The guild is already in raid mode
+    Ongoing,
+}
+
+/// The recent joins and the raids of every guild
+#[derive(Debug, Default)]
+pub struct RaidTracker {
+    joins: HashMap<GuildId, VecDeque<Join>>,
+    raids: HashMap<GuildId, Raid>,
+}
+
+impl RaidTracker {
+    /// Record a join at `at` and check whether it is part of a raid
+    ///
+    /// The raid has to be started with [`RaidTracker::start`] if it was
+    /// detected.
+    pub fn observe(
+        &mut self,
+        guild: GuildId,
+        user: UserId,
+        at: DateTime<Utc>,
+        invite: Option<&str>,
+        config: &RaidConfig,
+    ) -> Verdict {
+        if let Some(raid) = self.raids.get_mut(&guild) {
+            raid.last_join = at;
+            raid.joins += 1;
+            return Verdict::Ongoing;
+        }
+
+        let window = self.joins.entry(guild).or_default();
+        let since = at - Duration::seconds(config.interval);
+        while window.front().map(|j| j.at <= since).unwrap_or(false) {
+            window.pop_front();
+        }
+        window.push_back(Join {
+            at,
+            user,
+            invite: invite.map(str::to_string),
+        });
+
+        let through_invite = invite
+            .map(|code| {
+                window
+                    .iter()
+                    .filter(|j| j.invite.as_deref() == Some(code))
+                    .count()
+            })
+            .unwrap_or(0);
+        let interval = format_duration(Duration::seconds(config.interval));
+        let (reason, offending) = if window.len() > config.joins {
+            (
+                format!("{} members joined within {}", window.len(), interval),
+                None,
+            )
+        } else if through_invite > config.invite_joins {
+            (
+                format!(
+                    "{} members joined through the invite `{}` within {}",
+                    through_invite,
+                    invite.unwrap_or_default(),
+                    interval
+                ),
+                invite,
+            )
+        } else {
+            return Verdict::Normal;
+        };
+
+        let joins = self.joins.remove(&guild).unwrap_or_default();
+        // if a single invite was abused, only the members who used it are
+        // handled
+        Verdict::Started {
+            reason,
+            members: joins
+                .into_iter()
+                .filter(|j| offending.is_none() || j.invite.as_deref() == offending)
+                .map(|j| j.user)
+                .collect(),
+            invite: offending.map(str::to_string),
+        }
+    }
+
+    /// Put `guild` into raid mode and remember whether its invites are
+    /// paused. Returns `false` if it already is in raid mode.
+    pub fn start(&mut self, guild: GuildId, reason: String, pause_invites: bool) -> bool {
+        if self.raids.contains_key(&guild) {
+            return false;
+        }
+        let now = Utc::now();
+        self.joins.remove(&guild);
+        self.raids.insert(
+            guild,
+            Raid {
+                started: now,
+                last_join: now,
+                reason,
+                joins: 0,
+                previous_verification: None,
+                paused_invites: pause_invites,
+            },
+        );
+        true
+    }
+
+    pub fn get(&self, guild: GuildId) -> Option<&Raid> {
+        self.raids.get(&guild)
+    }
+
+    /// End raid mode in `guild`
+    pub fn end(&mut self, guild: GuildId) -> Option<Raid> {
+        self.raids.remove(&guild)
+    }
+
+    /// Continue a raid that was stored before a restart. Returns `false` if
+    /// `guild` already is in raid mode.
+    fn resume(&mut self, guild: GuildId, raid: Raid) -> bool {
+        if self.raids.contains_key(&guild) {
+            return false;
+        }
+        self.joins.remove(&guild);
+        self.raids.insert(guild, raid);
+        true
+    }
+}
+
+pub struct RaidGuard;
+
+impl RaidGuard {
+    /// Feed a join into the raid detection of its guild and handle the
+    /// member if the guild is in raid mode. Returns whether the member was
+    /// quarantined or kicked.
+    #[instrument(skip_all, name = "raid_guard", level = "debug")]
+    pub async fn on_join(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        user: UserId,
+        attribution: Option<&Attribution>,
+    ) -> bool {
+        let detection = data.guild(guild).raid;
+        let verdict = {
+            let mut tracker = data.raids.lock().unwrap();
+            match &detection {
+                Some(config) => tracker.observe(
+                    guild,
+                    user,
+                    Utc::now(),
+                    attribution.map(|a| a.code.as_str()),
+                    config,
+                ),
+                // raid mode can still be started manually
+                None => match tracker.get(guild) {
+                    Some(_) => tracker.observe(guild, user, Utc::now(), None, &Default::default()),
+                    None => Verdict::Normal,
+                },
+            }
+        };
+        let config = detection.unwrap_or_default();
+
+        match verdict {
+            Verdict::Normal => false,
+            Verdict::Ongoing => {
+                Self::store_joins(data, guild).await;
+                Self::handle_member(ctx, data, guild, user, &config).await
+            }
+            Verdict::Started {
+                reason,
+                members,
+                invite,
+            } => {
+                Self::start(ctx, data, guild, reason, invite.as_deref()).await;
+                let mut handled = false;
+                for member in members {
+                    let removed = Self::handle_member(ctx, data, guild, member, &config).await;
+                    if member == user {
+                        handled = removed;
+                    }
+                }
+                handled
+            }
+        }
+    }
+
+    /// Put `guild` into raid mode, pause its invites if `invite` was abused
+    /// and alert the moderators
+    pub async fn start(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        reason: String,
+        invite: Option<&str>,
+    ) -> bool {
+        // registered together with the raid, so ending raid mode while the
+        // invites are being paused resumes them
+        let pause = invite.is_some();
+        if !data
+            .raids
+            .lock()
+            .unwrap()
+            .start(guild, reason.clone(), pause)
+        {
+            return false;
+        }
+        event!(
+            Level::WARN,
+            guild = guild.0,
+            "guild {} entered raid mode: {}",
+            guild.0,
+            reason
+        );
+        let config = data.guild(guild).raid.unwrap_or_default();
+        Self::store(data, guild).await;
+
+        let paused = match invite {
+            Some(code) => Self::pause_invites(ctx, data, guild, code).await,
+            None => false,
+        };
+
+        if let Some(level) = config.verification {
+            let previous = guild.to_guild_cached(ctx).map(|g| g.verification_level);
+            match { guild }
+                .edit(ctx.http(), |g| g.verification_level(level))
+                .await
+            {
+                Ok(_) => {
+                    if let Some(raid) = data.raids.lock().unwrap().raids.get_mut(&guild) {
+                        raid.previous_verification = previous;
+                    }
+                }
+                Err(e) => {
+                    event!(Level::WARN, guild = guild.0, error = ?e, "failed to raise verification level of guild {}: {}", guild.0, e)
+                }
+            }
+        }
+        // what has to be restored is only known now
+        Self::store(data, guild).await;
+
+        Self::alert(ctx, data, guild, &config, |e| {
+            e.title("Raid mode enabled");
+            e.color(Color::RED);
+            e.description(&reason);
+            e.field(
+                "New members",
+                match config.action {
+                    RaidAction::None => "are not handled".to_string(),
+                    RaidAction::Quarantine if data.guild(guild).quarantine.is_some() => {
+                        "are quarantined".to_string()
+                    }
+                    RaidAction::Quarantine => format!(
+                        "are timed out for {}",
+                        format_duration(Duration::seconds(config.quarantine))
+                    ),
+                    RaidAction::Kick => "are kicked".to_string(),
+                },
+                false,
+            );
+            if paused {
+                e.field("Invites", "are paused until raid mode ends", false);
+            }
+            e.footer(|f| {
+                f.text(format!(
+                    "Raid mode ends after {} without joins or with /raidmode off",
+                    format_duration(Duration::seconds(config.cooldown))
+                ))
+            })
+        })
+        .await;
+
+        Self::schedule_end(ctx.clone(), data.clone(), guild, config.cooldown);
+        true
+    }
+
+    /// Pause the invites of `guild` because `code` was abused. Returns whether
+    /// they were paused by the raid.
+    async fn pause_invites(ctx: &Context, data: &Data, guild: GuildId, code: &str) -> bool {
+        let result = Self::set_invites_paused(ctx, guild, true).await;
+        let mut raids = data.raids.lock().unwrap();
+        let raid = raids.raids.get_mut(&guild);
+        match result {
+            // they were paused before, so they aren't resumed either
+            Ok(false) => {
+                if let Some(raid) = raid {
+                    raid.paused_invites = false;
+                }
+                false
+            }
+            Ok(true) => {
+                event!(
+                    Level::WARN,
+                    guild = guild.0,
+                    invite = code,
+                    "paused the invites of guild {}, invite {} was abused",
+                    guild.0,
+                    code
+                );
+                if raid.is_none() {
+                    // raid mode ended while the invites were paused
+                    drop(raids);
+                    let ctx = ctx.clone();
+                    tokio::spawn(async move { Self::resume_invites(&ctx, guild).await });
+                }
+                true
+            }
+            Err(e) => {
+                if let Some(raid) = raid {
+                    raid.paused_invites = false;
+                }
+                event!(Level::WARN, guild = guild.0, error = ?e, "failed to pause the invites of guild {}: {}", guild.0, e);
+                false
+            }
+        }
+    }
+
+    /// Resume the invites of `guild` after a raid. Returns whether they were
+    /// resumed.
+    async fn resume_invites(ctx: &Context, guild: GuildId) -> bool {
+        match Self::set_invites_paused(ctx, guild, false).await {
+            Ok(_) => true,
+            Err(e) => {
+                event!(Level::WARN, guild = guild.0, error = ?e, "failed to resume the invites of guild {}: {}", guild.0, e);
+                false
+            }
+        }
+    }
+
+    /// Add or remove the guild feature that pauses all invites. Returns
+    /// whether the feature was changed.
+    async fn set_invites_paused(ctx: &Context, guild: GuildId, paused: bool) -> Result<bool> {
+        let mut features = match guild.to_guild_cached(ctx) {
+            Some(guild) => guild.features,
+            None => guild.to_partial_guild(ctx.http()).await?.features,
+        };
+        if features.iter().any(|f| f == INVITES_DISABLED) == paused {
+            return Ok(false);
+        }
+        match paused {
+            true => features.push(INVITES_DISABLED.to_string()),
+            false => features.retain(|f| f != INVITES_DISABLED),
+        }
+        { guild }.edit(ctx.http(), |g| g.features(features)).await?;
+        Ok(true)
+    }
+
+    /// End raid mode in `guild`, restore its verification level and resume
+    /// its invites
+    pub async fn end(ctx: &Context, data: &Data, guild: GuildId, reason: &str) -> bool {
+        let raid = match data.raids.lock().unwrap().end(guild) {
+            Some(raid) => raid,
+            None => return false,
+        };
+        if let Err(e) = sqlx::query!(r#"DELETE FROM raids WHERE guild = $1"#, guild.0.to_string())
+            .execute(&data.pool)
+            .await
+        {
+            event!(Level::ERROR, guild = guild.0, error = ?e, "failed to delete the raid of guild {}: {}", guild.0, e);
+        }
+        event!(
+            Level::WARN,
+            guild = guild.0,
+            "guild {} left raid mode: {}",
+            guild.0,
+            reason
+        );
+
+        if let Some(level) = raid.previous_verification {
+            if let Err(e) = { guild }
+                .edit(ctx.http(), |g| g.verification_level(level))
+                .await
+            {
+                event!(Level::WARN, guild = guild.0, error = ?e, "failed to restore verification level of guild {}: {}", guild.0, e)
+            }
+        }
+
+        let resumed = match raid.paused_invites {
+            true => Some(Self::resume_invites(ctx, guild).await),
+            false => None,
+        };
+
+        let config = data.guild(guild).raid.unwrap_or_default();
+        Self::alert(ctx, data, guild, &config, |e| {
+            e.title("Raid mode disabled");
+            e.color(Color::DARK_GREEN);
+            e.description(reason);
+            e.field("Duration", format_duration(Utc::now() - raid.started), true);
+            e.field("Joins during the raid", raid.joins, true);
+            match resumed {
+                Some(true) => {
+                    e.field("Invites", "are resumed", false);
+                }
+                Some(false) => {
+                    e.field(
+                        "Invites",
+                        "could not be resumed, they are still paused",
+                        false,
+                    );
+                }
+                None => (),
+            }
+            e
+        })
+        .await;
+        true
+    }
+
+    /// Resume the raids of `guilds` that were ongoing when the bot stopped
+    #[instrument(skip_all, name = "raid_resume", level = "debug")]
+    pub async fn resume(ctx: &Context, data: &Data, guilds: &[GuildId]) {
+        let guilds = guilds.iter().map(|g| g.0.to_string()).collect::<Vec<_>>();
+        let raids = match sqlx::query!(
+            r#"
+        SELECT guild, reason, started_at, last_join, joins, previous_verification, paused_invites
+        FROM raids WHERE guild = ANY($1)
+        "#,
+            &guilds
+        )
+        .fetch_all(&data.pool)
+        .await
+        {
+            Ok(raids) => raids,
+            Err(e) => {
+                event!(Level::WARN, error = ?e, "failed to load ongoing raids: {}", e);
+                return;
+            }
+        };
+        for row in raids {
+            let guild = match row.guild.parse() {
+                Ok(guild) => GuildId(guild),
+                Err(_) => {
+                    event!(Level::WARN, "invalid raid in {}", row.guild);
+                    continue;
+                }
+            };
+            let raid = Raid {
+                started: row.started_at,
+                last_join: row.last_join,
+                reason: row.reason,
+                joins: row.joins as usize,
+                previous_verification: row.previous_verification.and_then(verification_level),
+                paused_invites: row.paused_invites,
+            };
+            // the shard of the guild may only have reconnected
+            if !data.raids.lock().unwrap().resume(guild, raid) {
+                continue;
+            }
+            event!(
+                Level::WARN,
+                guild = guild.0,
+                "resumed raid mode in guild {}",
+                guild.0
+            );
+            let cooldown = data.guild(guild).raid.unwrap_or_default().cooldown;
+            Self::schedule_end(ctx.clone(), data.clone(), guild, cooldown);
+        }
+    }
+
+    /// Store the raid of `guild`, so it is resumed after a restart
+    async fn store(data: &Data, guild: GuildId) {
+        let raid = match data.raids.lock().unwrap().get(guild) {
+            Some(raid) => raid.clone(),
+            None => return,
+        };
+        if let Err(e) = sqlx::query!(
+            r#"
+        INSERT INTO raids (guild, reason, started_at, last_join, joins, previous_verification, paused_invites)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (guild) DO UPDATE
+        SET last_join = EXCLUDED.last_join,
+        joins = EXCLUDED.joins,
+        previous_verification = EXCLUDED.previous_verification,
+        paused_invites = EXCLUDED.paused_invites
+        "#,
+            guild.0.to_string(),
+            raid.reason,
+            raid.started,
+            raid.last_join,
+            raid.joins as i64,
+            raid.previous_verification.map(|v| v.num() as i16),
+            raid.paused_invites,
+        )
+        .execute(&data.pool)
+        .await
+        {
+            event!(Level::ERROR, guild = guild.0, error = ?e, "failed to store the raid of guild {}: {}", guild.0, e);
+        }
+    }
+
+    /// Store the joins of the raid of `guild`, which delay its end
+    async fn store_joins(data: &Data, guild: GuildId) {
+        let (last_join, joins) = match data.raids.lock().unwrap().get(guild) {
+            Some(raid) => (raid.last_join, raid.joins),
+            None => return,
+        };
+        if let Err(e) = sqlx::query!(
+            r#"UPDATE raids SET last_join = $2, joins = $3 WHERE guild = $1"#,
+            guild.0.to_string(),
+            last_join,
+            joins as i64,
+        )
+        .execute(&data.pool)
+        .await
+        {
+            event!(Level::WARN, guild = guild.0, error = ?e, "failed to store the joins of the raid of guild {}: {}", guild.0, e);
+        }
+    }
+
+    /// End raid mode once no one joined for `cooldown` seconds
+    fn schedule_end(ctx: Context, data: Data, guild: GuildId, cooldown: i64) {
+        tokio::spawn(async move {
+            loop {
+                let last_join = match data.raids.lock().unwrap().get(guild) {
+                    Some(raid) => raid.last_join,
+                    None => return,
+                };
+                let end = last_join + Duration::seconds(cooldown);
+                let now = Utc::now();
+                if end <= now {
+                    let _work = data.shutdown.work();
+                    Self::end(
+                        &ctx,
+                        &data,
+                        guild,
+                        &format!(
+                            "No one joined for {}.",
+                            format_duration(Duration::seconds(cooldown))
+                        ),
+                    )
+                    .await;
+                    return;
+                }
+                if !data
+                    .shutdown
+                    .sleep((end - now).to_std().unwrap_or_default())
+                    .await
+                {
+                    return;
+                }
+            }
+        });
+    }
+
+    async fn handle_member(
+        ctx: &Context,
+        data: &Data,
+        guild: GuildId,
+        user: UserId,
+        config: &RaidConfig,
+    ) -> bool {
+        let bot = ctx.cache.current_user_id();
+        let reason = "Joined during a raid";
+        if data.in_maintenance(guild) {
+            let action = match config.action {
+                RaidAction::None => return false,
+                RaidAction::Quarantine => "quarantine",
+                RaidAction::Kick => "kick",
+            };
+            data.maintenance
+                .defer(&data.pool, guild, user, action, reason)
+                .await;
+            return false;
+        }
+        let entry = match config.action {
+            RaidAction::None => return false,
+            RaidAction::Quarantine if data.guild(guild).quarantine.is_some() => {
+                return match Quarantine::apply(ctx, data, guild, user, bot, reason).await {
+                    Ok(_) => true,
+                    Err(e) => {
+                        event!(Level::WARN, member = user.0, error = ?e, "failed to quarantine member {}: {}", user.0, e);
+                        false
+                    }
+                };
+            }
+            RaidAction::Quarantine => {
+                let until = Utc::now() + Duration::seconds(config.quarantine);
+                if let Err(e) = guild
+                    .edit_member(ctx.http(), user, |m| {
+                        m.disable_communication_until_datetime(until.into())
+                    })
+                    .await
+                {
+                    event!(Level::WARN, member = user.0, error = ?e, "failed to quarantine member {}: {}", user.0, e);
+                    return false;
+                }
+                Entry::new(guild, Action::Timeout, user, bot).until(until)
+            }
+            RaidAction::Kick => {
+                if let Err(e) = guild.kick_with_reason(ctx.http(), user, "Raid mode").await {
+                    event!(Level::WARN, member = user.0, error = ?e, "failed to kick member {}: {}", user.0, e);
+                    return false;
+                }
+                Entry::new(guild, Action::Kick, user, bot)
+            }
+        };
+        ModLog::log(ctx, data, entry.reason(reason)).await;
+        true
+    }
+
+    async fn alert<F>(ctx: &Context, data: &Data, guild: GuildId, config: &RaidConfig, f: F)
+    where
+        F: FnOnce(&mut CreateEmbed) -> &mut CreateEmbed,
+    {
+        let channel = match config.alert.or(data.guild(guild).modlog) {
+            Some(channel) => channel,
+            None => return,
+        };
+        if let Err(e) = channel
+            .send_message(ctx.http(), |m| {
+                if let Some(role) = config.alert_role {
+                    m.content(format!("<@&{}>", role));
+                    m.allowed_mentions(|a| a.roles(vec![role]));
+                }
+                m.embed(f)
+            })
+            .await
+        {
+            event!(Level::WARN, channel = channel.0, error = ?e, "failed to send raid alert: {}", e);
+        }
+    }
+}
+
+/// The verification level with the number Discord uses for it
+fn verification_level(num: i16) -> Option<VerificationLevel> {
+    match num {
+        0 => Some(VerificationLevel::None),
+        1 => Some(VerificationLevel::Low),
+        2 => Some(VerificationLevel::Medium),
+        3 => Some(VerificationLevel::High),
+        4 => Some(VerificationLevel::Higher),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const GUILD: GuildId = GuildId(1);
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn config() -> RaidConfig {
+        RaidConfig {
+            joins: 3,
+            interval: 10,
+            invite_joins: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flood_within_interval() {
+        let mut tracker = RaidTracker::default();
+        let config = config();
+        for user in 1..=3 {
+            assert_eq!(
+                tracker.observe(GUILD, UserId(user), at(user as i64), None, &config),
+                Verdict::Normal
+            );
+        }
+        assert_eq!(
+            tracker.observe(GUILD, UserId(4), at(4), None, &config),
+            Verdict::Started {
+                reason: "4 members joined within 10s".to_string(),
+                members: vec![UserId(1), UserId(2), UserId(3), UserId(4)],
+                invite: None,
+            }
+        );
+    }
+
+    #[test]
+    fn joins_outside_interval_are_forgotten() {
+        let mut tracker = RaidTracker::default();
+        let config = config();
+        for user in 0..10 {
+            assert_eq!(
+                tracker.observe(GUILD, UserId(user), at(user as i64 * 4), None, &config),
+                Verdict::Normal
+            );
+        }
+    }
+
+    #[test]
+    fn flood_does_not_pause_invites() {
+        let mut tracker = RaidTracker::default();
+        let config = config();
+        let invites = ["a", "b", "a", "b"];
+        let mut verdict = Verdict::Normal;
+        for (user, invite) in invites.into_iter().enumerate() {
+            verdict = tracker.observe(GUILD, UserId(user as u64), at(0), Some(invite), &config);
+        }
+        assert!(matches!(verdict, Verdict::Started { invite: None, .. }));
+    }
+
+    #[test]
+    fn abused_invite() {
+        let mut tracker = RaidTracker::default();
+        let config = RaidConfig {
+            joins: 10,
+            ..config()
+        };
+        let joins = [(1, Some("abc")), (2, Some("xyz")), (3, Some("abc"))];
+        for (user, invite) in joins {
+            assert_eq!(
+                tracker.observe(GUILD, UserId(user), at(0), invite, &config),
+                Verdict::Normal
+            );
+        }
+        // only the invite that crossed the limit and its members are handled
+        assert_eq!(
+            tracker.observe(GUILD, UserId(4), at(1), Some("abc"), &config),
+            Verdict::Started {
+                reason: "3 members joined through the invite `abc` within 10s".to_string(),
+                members: vec![UserId(1), UserId(3), UserId(4)],
+                invite: Some("abc".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn joins_during_raid() {
+        let mut tracker = RaidTracker::default();
+        let config = config();
+        assert!(tracker.start(GUILD, "test".to_string(), false));
+        assert!(!tracker.start(GUILD, "test".to_string(), false));
+        for second in [5, 6] {
+            assert_eq!(
+                tracker.observe(GUILD, UserId(1), at(second), None, &config),
+                Verdict::Ongoing
+            );
+        }
+        let raid = tracker.get(GUILD).unwrap();
+        assert_eq!(raid.joins, 2);
+        assert_eq!(raid.last_join, at(6));
+
+        assert!(tracker.end(GUILD).is_some());
+        assert!(tracker.end(GUILD).is_none());
+        assert_eq!(
+            tracker.observe(GUILD, UserId(1), at(7), None, &config),
+            Verdict::Normal
+        );
+    }
+
+    #[test]
+    fn resumed_raids() {
+        let mut tracker = RaidTracker::default();
+        let raid = Raid {
+            started: at(0),
+            last_join: at(5),
+            reason: "test".to_string(),
+            joins: 3,
+            previous_verification: verification_level(VerificationLevel::Low.num() as i16),
+            paused_invites: true,
+        };
+        assert!(tracker.resume(GUILD, raid.clone()));
+        // a shard that reconnects doesn't replace the ongoing raid
+        assert!(!tracker.resume(GUILD, raid));
+        assert!(!tracker.start(GUILD, "test".to_string(), false));
+        let raid = tracker.get(GUILD).unwrap();
+        assert_eq!(raid.previous_verification, Some(VerificationLevel::Low));
+        assert!(raid.paused_invites);
+        assert_eq!(
+            tracker.observe(GUILD, UserId(1), at(6), None, &config()),
+            Verdict::Ongoing
+        );
+    }
+
+    #[test]
+    fn guilds_are_tracked_separately() {
+        let mut tracker = RaidTracker::default();
+        let config = config();
+        for guild in 1..=4 {
+            for user in 1..=3 {
+                assert_eq!(
+                    tracker.observe(GuildId(guild), UserId(user), at(0), None, &config),
+                    Verdict::Normal
+                );
+            }
+        }
+        tracker.start(GuildId(5), "test".to_string(), false);
+        assert_eq!(
+            tracker.observe(GUILD, UserId(4), at(0), None, &config),
+            Verdict::Started {
+                reason: "4 members joined within 10s".to_string(),
+                members: vec![UserId(1), UserId(2), UserId(3), UserId(4)],
+                invite: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Join screening
+//!
+//! New members are checked for signs of throwaway accounts before their invite
+//! is attributed: a young account, the default avatar or a suspicious
+//! username. Members who fail a check are flagged in the mod log, quarantined
+//! or kicked, together with the invite they used.
+
+use chrono::{Duration, Utc};
+use poise::serenity_prelude::{CacheHttp, Context, Member};
+use regex::Regex;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::Level;
+
+use crate::{
+    invite::Attribution,
+    modlog::{Action, Entry, ModLog},
+    quarantine::Quarantine,
+    util::format_duration,
+    Data,
+};
+
+/// What happens to members who fail the screening
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreeningAction {
+    /// The member is only logged to the mod log
+    #[default]
+    Flag,
+    /// The member gets the quarantine role, or is timed out if the guild
+    /// hasn't configured one
+    Quarantine,
+    Kick,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScreeningConfig {
+    /// Accounts younger than this many seconds fail the screening
+    #[serde(default, rename = "minage")]
+    pub min_age: Option<i64>,
+    /// Whether accounts without an avatar fail the screening
+    #[serde(default, rename = "defaultavatar")]
+    pub default_avatar: bool,
+    /// Usernames that match any of these regular expressions fail the
+    /// screening
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    pub usernames: Vec<Regex>,
+    #[serde(default)]
+    pub action: ScreeningAction,
+    /// Length of the timeout in seconds if the guild has no quarantine role
+    #[serde(default = "default_quarantine")]
+    pub quarantine: i64,
+}
+
+fn default_quarantine() -> i64 {
+    24 * 60 * 60
+}
+
+/// The checks a member failed
+#[derive(Debug, Clone, Default)]
+pub struct Screening {
+    pub failed: Vec<String>,
+}
+
+impl Screening {
+    /// Check a member who just joined
+    pub fn screen(config: &ScreeningConfig, member: &Member) -> Self {
+        let user = &member.user;
+        let mut failed = Vec::new();
+
+        if let Some(min_age) = config.min_age {
+            let age = Utc::now() - *user.id.created_at();
+            if age < Duration::seconds(min_age) {
+                failed.push(format!("the account is only {} old", format_duration(age)));
+            }
+        }
+        if config.default_avatar && user.avatar.is_none() {
+            failed.push("the account has the default avatar".to_string());
+        }
+        if let Some(pattern) = config.usernames.iter().find(|p| p.is_match(&user.name)) {
+            failed.push(format!("the username matches `{}`", pattern));
+        }
+
+        Self { failed }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Flag, quarantine or kick a member who failed the screening. Returns
+    /// whether the member was quarantined or kicked.
+    #[instrument(skip_all, name = "screening", level = "debug")]
+    pub async fn enforce(
+        &self,
+        ctx: &Context,
+        data: &Data,
+        config: &ScreeningConfig,
+        member: &Member,
+        attribution: Option<&Attribution>,
+    ) -> bool {
+        if self.passed() {
+            return false;
+        }
+        let guild = member.guild_id;
+        let user = member.user.id;
+        event!(
+            Level::INFO,
+            member = user.0,
+            guild = guild.0,
+            "member {} failed the join screening of guild {}: {}",
+            user.0,
+            guild.0,
+            self.failed.join(", ")
+        );
+
+        let mut reason = format!("Join screening: {}.", self.failed.join(", "));
+        match attribution {
+            Some(attribution) => reason.push_str(&format!(
+                " Joined with `{}` from <@{}>.",
+                attribution.code, attribution.invite.inviter
+            )),
+            None => reason.push_str(" The invite is unknown."),
+        }
+
+        let bot = ctx.cache.current_user_id();
+        if data.in_maintenance(guild) {
+            let action = match config.action {
+                ScreeningAction::Flag => None,
+                ScreeningAction::Quarantine => Some("quarantine"),
+                ScreeningAction::Kick => Some("kick"),
+            };
+            // flagging only logs, so it happens anyway
+            if let Some(action) = action {
+                data.maintenance
+                    .defer(&data.pool, guild, user, action, &reason)
+                    .await;
+                return false;
+            }
+        }
+        let entry = match config.action {
+            ScreeningAction::Flag => Entry::new(guild, Action::Flag, user, bot),
+            ScreeningAction::Quarantine if data.guild(guild).quarantine.is_some() => {
+                return match Quarantine::apply(ctx, data, guild, user, bot, &reason).await {
+                    Ok(_) => true,
+                    Err(e) => {
+                        event!(Level::WARN, member = user.0, error = ?e, "failed to quarantine member {}: {}", user.0, e);
+                        false
+                    }
+                };
+            }
+            ScreeningAction::Quarantine => {
+                let until = Utc::now() + Duration::seconds(config.quarantine);
+                if let Err(e) = guild
+                    .edit_member(ctx.http(), user, |m| {
+                        m.disable_communication_until_datetime(until.into())
+                    })
+                    .await
+                {
+                    event!(Level::WARN, member = user.0, error = ?e, "failed to quarantine member {}: {}", user.0, e);
+                    return false;
+                }
+                Entry::new(guild, Action::Timeout, user, bot).until(until)
+            }
+            ScreeningAction::Kick => {
+                if let Err(e) = guild
+                    .kick_with_reason(ctx.http(), user, "Join screening")
+                    .await
+                {
+                    event!(Level::WARN, member = user.0, error = ?e, "failed to kick member {}: {}", user.0, e);
+                    return false;
+                }
+                Entry::new(guild, Action::Kick, user, bot)
+            }
+        };
+        ModLog::log(ctx, data, entry.reason(reason)).await;
+        // flagged members stay in the guild as they are
+        config.action != ScreeningAction::Flag
+    }
+}
@@ -0,0 +1,574 @@
+//! Guild settings stored in the database
+//!
+//! Some settings of a guild can be changed with `/config` instead of the
+//! config file. They are stored in the `guild_settings` table and take
+//! precedence over the config file. All settings are loaded at start up and
+//! the settings of a guild are loaded again whenever one of them is written.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use poise::serenity_prelude::{ChannelId, ChannelType, Guild, GuildId, Role, RoleId, UserId};
+use sqlx::PgPool;
+use tracing::Level;
+
+use crate::{
+    config::GuildConfig,
+    messagelog::MessageLogConfig,
+    quarantine::{QuarantineConfig, Remedy},
+};
+
+/// The key per-guild maintenance mode is stored with
+const MAINTENANCE: &str = "maintenance";
+
+/// A setting that can be changed with `/config`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, poise::ChoiceParameter)]
+pub enum Setting {
+    #[name = "modlog"]
+    Modlog,
+    #[name = "appeals"]
+    Appeals,
+    #[name = "auditlog"]
+    Auditlog,
+    #[name = "messagelog"]
+    Messagelog,
+    #[name = "quarantine"]
+    Quarantine,
+    #[name = "untracked"]
+    Untracked,
+    #[name = "automod.sticker"]
+    AutomodSticker,
+    #[name = "automod.spam"]
+    AutomodSpam,
+    #[name = "automod.invites"]
+    AutomodInvites,
+    #[name = "automod.links"]
+    AutomodLinks,
+    #[name = "automod.filter"]
+    AutomodFilter,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 11] = [
+        Setting::Modlog,
+        Setting::Appeals,
+        Setting::Auditlog,
+        Setting::Messagelog,
+        Setting::Quarantine,
+        Setting::Untracked,
+        Setting::AutomodSticker,
+        Setting::AutomodSpam,
+        Setting::AutomodInvites,
+        Setting::AutomodLinks,
+        Setting::AutomodFilter,
+    ];
+
+    /// What values the setting accepts
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Setting::Modlog | Setting::Appeals | Setting::Auditlog | Setting::Messagelog => {
+                "a text channel"
+            }
+            Setting::Quarantine => "a role",
+            Setting::Untracked => "`kick` or `quarantine`",
+            Setting::AutomodSticker
+            | Setting::AutomodSpam
+            | Setting::AutomodInvites
+            | Setting::AutomodLinks
+            | Setting::AutomodFilter => "`on` or `off`",
+        }
+    }
+
+    /// The value of this setting in `config`, formatted for Discord
+    pub fn current(&self, config: &GuildConfig) -> String {
+        let channel = |c: Option<ChannelId>| {
+            c.map(|c| format!("<#{}>", c))
+                .unwrap_or_else(|| "not set".to_string())
+        };
+        let toggle = |enabled: bool| match enabled {
+            true => "on".to_string(),
+            false => "off".to_string(),
+        };
+        match self {
+            Setting::Modlog => channel(config.modlog),
+            Setting::Appeals => channel(config.appeals),
+            Setting::Auditlog => channel(config.auditlog),
+            Setting::Messagelog => channel(config.messagelog.as_ref().map(|m| m.channel)),
+            Setting::Quarantine => config
+                .quarantine
+                .as_ref()
+                .map(|q| format!("<@&{}>", q.role))
+                .unwrap_or_else(|| "not set".to_string()),
+            Setting::Untracked => config.untracked.to_string(),
+            Setting::AutomodSticker => toggle(config.automod.sticker.settings.enabled),
+            Setting::AutomodSpam => toggle(config.automod.spam.settings.enabled),
+            Setting::AutomodInvites => toggle(config.automod.invites.settings.enabled),
+            Setting::AutomodLinks => toggle(config.automod.links.settings.enabled),
+            Setting::AutomodFilter => toggle(config.automod.filter.settings.enabled),
+        }
+    }
+}
+
+/// The settings of a guild that were changed with `/config`
+///
+/// Settings that are [`None`] are taken from the config file.
+#[derive(Debug, Clone, Default)]
+pub struct GuildSettings {
+    pub modlog: Option<ChannelId>,
+    pub appeals: Option<ChannelId>,
+    pub auditlog: Option<ChannelId>,
+    pub messagelog: Option<ChannelId>,
+    pub quarantine: Option<RoleId>,
+    pub untracked: Option<Remedy>,
+    pub sticker: Option<bool>,
+    pub spam: Option<bool>,
+    pub invites: Option<bool>,
+    pub links: Option<bool>,
+    pub filter: Option<bool>,
+    /// Set by the owners with `/admin maintenance`, so it isn't a [`Setting`]
+    /// that the guild could change back
+    pub maintenance: Option<bool>,
+}
+
+impl GuildSettings {
+    /// Parse `value` and change `setting` to it
+    ///
+    /// Channels and roles can be given as a mention or as an ID.
+    pub fn set(&mut self, setting: Setting, value: &str) -> anyhow::Result<()> {
+        let value = value.trim();
+        match setting {
+            Setting::Modlog => self.modlog = Some(parse_channel(value)?),
+            Setting::Appeals => self.appeals = Some(parse_channel(value)?),
+            Setting::Auditlog => self.auditlog = Some(parse_channel(value)?),
+            Setting::Messagelog => self.messagelog = Some(parse_channel(value)?),
+            Setting::Quarantine => self.quarantine = Some(parse_role(value)?),
+            Setting::Untracked => {
+                self.untracked = Some(match value.to_lowercase().as_str() {
+                    "kick" => Remedy::Kick,
+                    "quarantine" => Remedy::Quarantine,
+                    _ => return Err(anyhow!("`{}` is neither `kick` nor `quarantine`", value)),
+                })
+            }
+            Setting::AutomodSticker => self.sticker = Some(parse_toggle(value)?),
+            Setting::AutomodSpam => self.spam = Some(parse_toggle(value)?),
+            Setting::AutomodInvites => self.invites = Some(parse_toggle(value)?),
+            Setting::AutomodLinks => self.links = Some(parse_toggle(value)?),
+            Setting::AutomodFilter => self.filter = Some(parse_toggle(value)?),
+        }
+        Ok(())
+    }
+
+    /// The value of `setting` as it is stored in the database
+    pub fn get(&self, setting: Setting) -> Option<String> {
+        match setting {
+            Setting::Modlog => self.modlog.map(|c| c.0.to_string()),
+            Setting::Appeals => self.appeals.map(|c| c.0.to_string()),
+            Setting::Auditlog => self.auditlog.map(|c| c.0.to_string()),
+            Setting::Messagelog => self.messagelog.map(|c| c.0.to_string()),
+            Setting::Quarantine => self.quarantine.map(|r| r.0.to_string()),
+            Setting::Untracked => self.untracked.map(|r| r.to_string()),
+            Setting::AutomodSticker => self.sticker.map(|t| t.to_string()),
+            Setting::AutomodSpam => self.spam.map(|t| t.to_string()),
+            Setting::AutomodInvites => self.invites.map(|t| t.to_string()),
+            Setting::AutomodLinks => self.links.map(|t| t.to_string()),
+            Setting::AutomodFilter => self.filter.map(|t| t.to_string()),
+        }
+    }
+
+    /// Check that the channels and roles of `setting` exist in `guild` and can
+    /// be used
+    pub fn validate(&self, setting: Setting, guild: &Guild) -> anyhow::Result<()> {
+        let channel = match setting {
+            Setting::Modlog => self.modlog,
+            Setting::Appeals => self.appeals,
+            Setting::Auditlog => self.auditlog,
+            Setting::Messagelog => self.messagelog,
+            _ => None,
+        };
+        if let Some(channel) = channel {
+            match guild.channels.get(&channel).and_then(|c| c.clone().guild()) {
+                Some(c) if matches!(c.kind, ChannelType::Text | ChannelType::News) => {}
+                Some(_) => return Err(anyhow!("<#{}> isn't a text channel", channel)),
+                None => return Err(anyhow!("There is no channel {} on this server", channel)),
+            }
+        }
+        if let (Setting::Quarantine, Some(role)) = (setting, self.quarantine) {
+            validate_quarantine_role(guild.id, &guild.roles, role)?;
+        }
+        Ok(())
+    }
+
+    /// Override the settings of `config` with the ones that were changed
+    pub fn apply(&self, config: &mut GuildConfig) {
+        if let Some(modlog) = self.modlog {
+            config.modlog = Some(modlog);
+        }
+        if let Some(appeals) = self.appeals {
+            config.appeals = Some(appeals);
+        }
+        if let Some(auditlog) = self.auditlog {
+            config.auditlog = Some(auditlog);
+        }
+        if let Some(channel) = self.messagelog {
+            match &mut config.messagelog {
+                Some(messagelog) => messagelog.channel = channel,
+                None => {
+                    config.messagelog = Some(MessageLogConfig {
+                        channel,
+                        ignored: Default::default(),
+                    })
+                }
+            }
+        }
+        if let Some(role) = self.quarantine {
+            config.quarantine = Some(QuarantineConfig { role });
+        }
+        if let Some(untracked) = self.untracked {
+            config.untracked = untracked;
+        }
+        if let Some(maintenance) = self.maintenance {
+            config.maintenance = maintenance;
+        }
+        let automod = &mut config.automod;
+        for (enabled, settings) in [
+            (self.sticker, &mut automod.sticker.settings),
+            (self.spam, &mut automod.spam.settings),
+            (self.invites, &mut automod.invites.settings),
+            (self.links, &mut automod.links.settings),
+            (self.filter, &mut automod.filter.settings),
+        ] {
+            if let Some(enabled) = enabled {
+                settings.enabled = enabled;
+            }
+        }
+    }
+}
+
+/// Check that `role` can be given to members and taken away again
+fn validate_quarantine_role(
+    guild: GuildId,
+    roles: &HashMap<RoleId, Role>,
+    role: RoleId,
+) -> anyhow::Result<()> {
+    match roles.get(&role) {
+        _ if role.0 == guild.0 => Err(anyhow!("The @everyone role can't be the quarantine role")),
+        Some(r) if r.managed => Err(anyhow!("<@&{}> is managed by an integration", role)),
+        Some(_) => Ok(()),
+        None => Err(anyhow!("There is no role {} on this server", role)),
+    }
+}
+
+fn parse_channel(value: &str) -> anyhow::Result<ChannelId> {
+    value
+        .trim_start_matches("<#")
+        .trim_end_matches('>')
+        .parse()
+        .map(ChannelId)
+        .map_err(|_| anyhow!("`{}` is not a channel", value))
+}
+
+fn parse_role(value: &str) -> anyhow::Result<RoleId> {
+    value
+        .trim_start_matches("<@&")
+        .trim_end_matches('>')
+        .parse()
+        .map(RoleId)
+        .map_err(|_| anyhow!("`{}` is not a role", value))
+}
+
+fn parse_toggle(value: &str) -> anyhow::Result<bool> {
+    match value.to_lowercase().as_str() {
+        "on" | "true" | "yes" | "enabled" => Ok(true),
+        "off" | "false" | "no" | "disabled" => Ok(false),
+        _ => Err(anyhow!("`{}` is neither `on` nor `off`", value)),
+    }
+}
+
+/// The settings of all guilds
+#[derive(Debug, Clone, Default)]
+pub struct SettingsStore(Arc<RwLock<HashMap<GuildId, GuildSettings>>>);
+
+impl SettingsStore {
+    /// The changed settings of `guild`
+    pub fn get(&self, guild: GuildId) -> GuildSettings {
+        self.0
+            .read()
+            .unwrap()
+            .get(&guild)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Load the settings of all guilds
+    pub async fn load(&self, pool: &PgPool) -> sqlx::Result<()> {
+        let rows = sqlx::query!(r#"SELECT guild, key, value FROM guild_settings"#)
+            .fetch_all(pool)
+            .await?;
+        let mut settings: HashMap<GuildId, GuildSettings> = HashMap::new();
+        for row in rows {
+            let guild = match row.guild.parse() {
+                Ok(guild) => GuildId(guild),
+                Err(_) => continue,
+            };
+            Self::load_row(settings.entry(guild).or_default(), &row.key, &row.value);
+        }
+        *self.0.write().unwrap() = settings;
+        Ok(())
+    }
+
+    /// Store a setting of `guild` and reload the settings of the guild
+    ///
+    /// The setting is stored once this returns, even if the settings couldn't
+    /// be reloaded.
+    pub async fn set(
+        &self,
+        pool: &PgPool,
+        guild: GuildId,
+        setting: Setting,
+        value: &str,
+        by: UserId,
+    ) -> sqlx::Result<()> {
+        self.store(pool, guild, setting.name(), value, by).await
+    }
+
+    /// Turn maintenance mode in `guild` on or off, like [`SettingsStore::set`]
+    pub async fn set_maintenance(
+        &self,
+        pool: &PgPool,
+        guild: GuildId,
+        enabled: bool,
+        by: UserId,
+    ) -> sqlx::Result<()> {
+        self.store(pool, guild, MAINTENANCE, &enabled.to_string(), by)
+            .await
+    }
+
+    async fn store(
+        &self,
+        pool: &PgPool,
+        guild: GuildId,
+        key: &str,
+        value: &str,
+        by: UserId,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"
+        INSERT INTO guild_settings (guild, key, value, updated_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (guild, key) DO UPDATE
+        SET value = EXCLUDED.value,
+        updated_by = EXCLUDED.updated_by,
+        updated_at = now()
+        "#,
+            guild.0.to_string(),
+            key,
+            value,
+            by.0.to_string(),
+        )
+        .execute(pool)
+        .await?;
+        self.invalidate(pool, guild).await;
+        Ok(())
+    }
+
+    /// Remove a setting of `guild`, so the config file applies again
+    ///
+    /// Returns whether the setting was changed before.
+    pub async fn reset(
+        &self,
+        pool: &PgPool,
+        guild: GuildId,
+        setting: Setting,
+    ) -> sqlx::Result<bool> {
+        let deleted = sqlx::query!(
+            r#"DELETE FROM guild_settings WHERE guild = $1 AND key = $2"#,
+            guild.0.to_string(),
+            setting.name(),
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+        self.invalidate(pool, guild).await;
+        Ok(deleted > 0)
+    }
+
+    /// Load the settings of `guild` again and replace the cached ones. The
+    /// cached settings are kept if they can't be loaded.
+    async fn invalidate(&self, pool: &PgPool, guild: GuildId) {
+        let rows = match sqlx::query!(
+            r#"SELECT key, value FROM guild_settings WHERE guild = $1"#,
+            guild.0.to_string(),
+        )
+        .fetch_all(pool)
+        .await
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                event!(Level::ERROR, guild = guild.0, error = ?e, "failed to reload the settings of guild {}: {}", guild.0, e);
+                return;
+            }
+        };
+        let mut settings = GuildSettings::default();
+        for row in rows {
+            Self::load_row(&mut settings, &row.key, &row.value);
+        }
+        self.0.write().unwrap().insert(guild, settings);
+    }
+
+    fn load_row(settings: &mut GuildSettings, key: &str, value: &str) {
+        let result = match key.parse::<Setting>() {
+            Ok(setting) => settings.set(setting, value),
+            Err(_) if key == MAINTENANCE => {
+                parse_toggle(value).map(|enabled| settings.maintenance = Some(enabled))
+            }
+            Err(_) => Err(anyhow!("unknown setting")),
+        };
+        if let Err(e) = result {
+            event!(Level::WARN, key = key, error = ?e, "ignoring stored setting {}: {}", key, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serenity::json::json;
+
+    use super::*;
+
+    const GUILD: GuildId = GuildId(1);
+
+    fn roles() -> HashMap<RoleId, Role> {
+        [
+            (1, "@everyone", false),
+            (2, "Quarantine", false),
+            (3, "Bot", true),
+        ]
+        .into_iter()
+        .map(|(id, name, managed)| {
+            let role = Role::deserialize(json!({
+                "id": id.to_string(),
+                "guild_id": GUILD.0.to_string(),
+                "color": 0,
+                "hoist": false,
+                "managed": managed,
+                "name": name,
+                "permissions": "0",
+                "position": 0,
+            }))
+            .unwrap();
+            (RoleId(id), role)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn channel_mention_or_id() {
+        let mut settings = GuildSettings::default();
+        settings.set(Setting::Modlog, "<#123>").unwrap();
+        assert_eq!(settings.modlog, Some(ChannelId(123)));
+        settings.set(Setting::Modlog, " 456 ").unwrap();
+        assert_eq!(settings.modlog, Some(ChannelId(456)));
+        assert!(settings.set(Setting::Modlog, "<@&123>").is_err());
+        assert!(settings.set(Setting::Modlog, "modlog").is_err());
+        assert_eq!(settings.get(Setting::Modlog), Some("456".to_string()));
+    }
+
+    #[test]
+    fn role_mention_or_id() {
+        let mut settings = GuildSettings::default();
+        settings.set(Setting::Quarantine, "<@&123>").unwrap();
+        assert_eq!(settings.quarantine, Some(RoleId(123)));
+        settings.set(Setting::Quarantine, "456").unwrap();
+        assert_eq!(settings.quarantine, Some(RoleId(456)));
+        assert!(settings.set(Setting::Quarantine, "<#123>").is_err());
+        assert!(settings.set(Setting::Quarantine, "@quarantine").is_err());
+    }
+
+    #[test]
+    fn toggle_aliases() {
+        let mut settings = GuildSettings::default();
+        for value in ["on", "True", "YES", "enabled"] {
+            settings.set(Setting::AutomodSpam, value).unwrap();
+            assert_eq!(settings.spam, Some(true), "{}", value);
+        }
+        for value in ["off", "false", "No", "DISABLED"] {
+            settings.set(Setting::AutomodSpam, value).unwrap();
+            assert_eq!(settings.spam, Some(false), "{}", value);
+        }
+        assert!(settings.set(Setting::AutomodSpam, "maybe").is_err());
+        // the previous value is kept
+        assert_eq!(settings.spam, Some(false));
+    }
+
+    #[test]
+    fn untracked_remedy() {
+        let mut settings = GuildSettings::default();
+        settings.set(Setting::Untracked, "Quarantine").unwrap();
+        assert_eq!(settings.untracked, Some(Remedy::Quarantine));
+        settings.set(Setting::Untracked, "kick").unwrap();
+        assert_eq!(settings.untracked, Some(Remedy::Kick));
+        assert!(settings.set(Setting::Untracked, "ban").is_err());
+    }
+
+    #[test]
+    fn quarantine_role() {
+        let roles = roles();
+        assert!(validate_quarantine_role(GUILD, &roles, RoleId(2)).is_ok());
+        // @everyone has the id of the guild
+        assert!(validate_quarantine_role(GUILD, &roles, RoleId(1)).is_err());
+        assert!(validate_quarantine_role(GUILD, &roles, RoleId(3)).is_err());
+        assert!(validate_quarantine_role(GUILD, &roles, RoleId(4)).is_err());
+    }
+
+    #[test]
+    fn apply_overrides_config_file() {
+        let mut config = GuildConfig {
+            modlog: Some(ChannelId(1)),
+            auditlog: Some(ChannelId(2)),
+            messagelog: Some(MessageLogConfig {
+                channel: ChannelId(3),
+                ignored: [ChannelId(4)].into(),
+            }),
+            ..Default::default()
+        };
+        config.automod.spam.settings.enabled = true;
+        let settings = GuildSettings {
+            modlog: Some(ChannelId(10)),
+            messagelog: Some(ChannelId(30)),
+            quarantine: Some(RoleId(5)),
+            untracked: Some(Remedy::Quarantine),
+            spam: Some(false),
+            links: Some(true),
+            ..Default::default()
+        };
+        settings.apply(&mut config);
+
+        assert_eq!(config.modlog, Some(ChannelId(10)));
+        // settings that weren't changed are taken from the config file
+        assert_eq!(config.auditlog, Some(ChannelId(2)));
+        assert_eq!(config.appeals, None);
+        let messagelog = config.messagelog.unwrap();
+        assert_eq!(messagelog.channel, ChannelId(30));
+        assert!(messagelog.ignored.contains(&ChannelId(4)));
+        assert_eq!(config.quarantine.map(|q| q.role), Some(RoleId(5)));
+        assert_eq!(config.untracked, Remedy::Quarantine);
+        assert!(!config.automod.spam.settings.enabled);
+        assert!(config.automod.links.settings.enabled);
+    }
+
+    #[test]
+    fn apply_adds_message_log() {
+        let mut config = GuildConfig::default();
+        GuildSettings {
+            messagelog: Some(ChannelId(3)),
+            ..Default::default()
+        }
+        .apply(&mut config);
+        let messagelog = config.messagelog.unwrap();
+        assert_eq!(messagelog.channel, ChannelId(3));
+        assert!(messagelog.ignored.is_empty());
+    }
+}
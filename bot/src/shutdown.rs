@@ -0,0 +1,148 @@
+//! Graceful shutdown
+//!
+//! When the bot is asked to stop, it stops taking commands, lets the work
+//! that is already running finish for up to `shutdown.timeout` seconds and
+//! only then disconnects from the gateway. Timers like the end of raid mode
+//! are dropped instead of waiting for them, raids and pending verifications
+//! are resumed from the database on the next start.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio_util::{
+    sync::CancellationToken,
+    task::{task_tracker::TaskTrackerToken, TaskTracker},
+};
+use tracing::Level;
+
+use crate::{Context, Result};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ShutdownConfig {
+    /// How many seconds running work may take before the bot disconnects
+    /// anyway. Docker kills the container 10 seconds after asking it to stop,
+    /// so raise `docker stop --time` together with this.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_timeout(),
+        }
+    }
+}
+
+fn default_timeout() -> u64 {
+    8
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    work: TaskTracker,
+}
+
+impl Shutdown {
+    /// Mark running work that should finish before the bot disconnects. The
+    /// work is done once the returned guard is dropped.
+    pub fn work(&self) -> TaskTrackerToken {
+        self.work.token()
+    }
+
+    /// Spawn a task the bot waits for before it disconnects
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.work.spawn(task);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once the shutdown started
+    pub async fn started(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Sleep unless the bot shuts down first
+    ///
+    /// Returns `false` if the sleep was cut short, in which case the caller
+    /// should give up instead of acting.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => true,
+            _ = self.token.cancelled() => false,
+        }
+    }
+
+    /// Start the shutdown and wait until all running work is done, or until
+    /// `timeout` passed
+    pub async fn drain(&self, timeout: Duration) {
+        self.token.cancel();
+        self.work.close();
+        event!(
+            Level::INFO,
+            running = self.work.len(),
+            "shutting down, waiting for {} running task(s)",
+            self.work.len()
+        );
+        if tokio::time::timeout(timeout, self.work.wait())
+            .await
+            .is_err()
+        {
+            event!(
+                Level::WARN,
+                running = self.work.len(),
+                "{} task(s) did not finish within {}s",
+                self.work.len(),
+                timeout.as_secs()
+            );
+        }
+    }
+}
+
+/// Refuse new commands once the shutdown started
+pub async fn accepts_commands(ctx: Context<'_>) -> Result<bool> {
+    if !ctx.data().shutdown.is_shutting_down() {
+        return Ok(true);
+    }
+    ctx.send(|m| {
+        m.content("The bot is restarting, try again in a minute.")
+            .ephemeral(true)
+    })
+    .await?;
+    Ok(false)
+}
+
+/// Resolves once the bot is asked to stop, with ctrl-c or with SIGTERM, which
+/// is what `docker stop` sends
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => event!(Level::INFO, "received SIGTERM"),
+                    result = tokio::signal::ctrl_c() => {
+                        if let Err(e) = result {
+                            panic!("Unable to listen for shutdown signal: {}", e);
+                        }
+                    }
+                }
+                return;
+            }
+            Err(e) => {
+                event!(Level::WARN, error = ?e, "cannot listen for SIGTERM, only ctrl-c stops the bot gracefully: {}", e)
+            }
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        panic!("Unable to listen for shutdown signal: {}", e);
+    }
+}
@@ -0,0 +1,254 @@
+//! Templates for sanction notifications
+//!
+//! Templates are configured per guild and locale, either in `Bot.toml` or with
+//! the `/template` command. Templates from the database take precedence over
+//! the ones in the config file. Placeholders are written as `{name}`, literal
+//! braces as `{{` and `}}`. `{appeal}` is replaced with the `appeal` text of
+//! the template if the sanction can be appealed, and with nothing otherwise.
+
+use std::collections::HashMap;
+
+use poise::serenity_prelude::{Context, GuildId, UserId};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::Level;
+
+use crate::Data;
+
+/// The locale that is used if there is no template for the locale of the user
+pub const DEFAULT_LOCALE: &str = "en-US";
+
+/// All locales Discord knows about
+pub const LOCALES: &[&str] = &[
+    "id", "da", "de", "en-GB", "en-US", "es-ES", "fr", "hr", "it", "lt", "hu", "nl", "no", "pl",
+    "pt-BR", "ro", "fi", "sv-SE", "vi", "tr", "cs", "el", "bg", "ru", "uk", "hi", "th", "zh-CN",
+    "ja", "zh-TW", "ko",
+];
+
+/// The text of `{appeal}` if a template doesn't set one
+const DEFAULT_APPEAL: &str =
+    "If you think this wasn't justified, you can appeal with the button below.";
+
+/// The placeholders a template can use
+pub const PLACEHOLDERS: &[&str] = &["username", "reason", "penalty", "guild", "appeal"];
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Template {
+    #[serde(default)]
+    pub title: Option<String>,
+    pub body: String,
+    #[serde(default)]
+    pub footer: Option<String>,
+    /// What `{appeal}` is replaced with, in the language of the template
+    #[serde(default)]
+    pub appeal: Option<String>,
+}
+
+impl Template {
+    /// The text that is used if a guild has no (valid) template
+    pub fn fallback() -> Self {
+        Self {
+            title: Some("You've been sanctioned".to_string()),
+            body: "Hey {username},\n\nI'm sorry to tell you, but you've been sanctioned for \
+                   {reason}.\nYour penalty is {penalty}.\n\n{appeal}"
+                .to_string(),
+            footer: Some("you probably deserved it".to_string()),
+            appeal: None,
+        }
+    }
+
+    /// The text that tells the user how to appeal
+    pub fn appeal_text(&self) -> &str {
+        self.appeal.as_deref().unwrap_or(DEFAULT_APPEAL)
+    }
+
+    /// Render this template with example values
+    pub fn preview(&self) -> anyhow::Result<Self> {
+        self.render(&Placeholders {
+            appeal: self.appeal_text().to_string(),
+            ..Placeholders::example()
+        })
+    }
+
+    /// Fill in the placeholders of all parts of this template
+    pub fn render(&self, values: &Placeholders) -> anyhow::Result<Self> {
+        Ok(Self {
+            title: self
+                .title
+                .as_deref()
+                .map(|t| render(t, values))
+                .transpose()?,
+            body: render(&self.body, values)?,
+            footer: self
+                .footer
+                .as_deref()
+                .map(|f| render(f, values))
+                .transpose()?,
+            appeal: self.appeal.clone(),
+        })
+    }
+
+    /// Find the template for a notification in `guild` for `user`
+    ///
+    /// The locale of the user is tried first, then their language without the
+    /// region, then the locale of the guild and finally [`DEFAULT_LOCALE`].
+    pub async fn resolve(ctx: &Context, data: &Data, guild: GuildId, user: UserId) -> Self {
+        let mut locales = Vec::new();
+        match user_locale(&data.pool, user).await {
+            Ok(Some(locale)) => {
+                if let Some((language, _)) = locale.split_once('-') {
+                    let language = language.to_string();
+                    locales.push(locale);
+                    locales.push(language);
+                } else {
+                    locales.push(locale);
+                }
+            }
+            Ok(None) => (),
+            Err(e) => {
+                event!(Level::WARN, user = user.0, error = ?e, "failed to load locale of user {}: {}", user.0, e)
+            }
+        }
+        if let Some(locale) = guild.to_guild_cached(ctx).map(|g| g.preferred_locale) {
+            locales.push(locale);
+        }
+        locales.push(DEFAULT_LOCALE.to_string());
+
+        let mut templates = data.guild(guild).templates;
+        match stored_templates(&data.pool, guild).await {
+            Ok(stored) => templates.extend(stored),
+            Err(e) => {
+                event!(Level::WARN, guild = guild.0, error = ?e, "failed to load templates of guild {}: {}", guild.0, e)
+            }
+        }
+
+        locales
+            .iter()
+            .find_map(|locale| templates.remove(locale))
+            .unwrap_or_else(Self::fallback)
+    }
+}
+
+/// The values that are filled into a template
+#[derive(Debug, Clone, Default)]
+pub struct Placeholders {
+    pub username: String,
+    pub reason: String,
+    pub penalty: String,
+    pub guild: String,
+    pub appeal: String,
+}
+
+impl Placeholders {
+    /// Placeholders with example values, used to check templates
+    pub fn example() -> Self {
+        Self {
+            username: "Wumpus".to_string(),
+            reason: "sending a default sticker".to_string(),
+            penalty: "a timeout until <t:1543392060:R>".to_string(),
+            guild: "PwnHub".to_string(),
+            appeal: DEFAULT_APPEAL.to_string(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "username" => Some(&self.username),
+            "reason" => Some(&self.reason),
+            "penalty" => Some(&self.penalty),
+            "guild" => Some(&self.guild),
+            "appeal" => Some(&self.appeal),
+            _ => None,
+        }
+    }
+}
+
+/// Replace all placeholders in `template`
+///
+/// Fails on unknown placeholders and unbalanced braces.
+pub fn render(template: &str, values: &Placeholders) -> anyhow::Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                rendered.push('{');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(anyhow!("unclosed placeholder `{{{}`", name)),
+                    }
+                }
+                let value = values
+                    .get(name.trim())
+                    .ok_or_else(|| anyhow!("unknown placeholder `{{{}}}`", name))?;
+                rendered.push_str(value);
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                rendered.push('}');
+            }
+            '}' => return Err(anyhow!("unmatched `}}`, use `}}}}` for a literal brace")),
+            c => rendered.push(c),
+        }
+    }
+    Ok(rendered)
+}
+
+/// Remember the locale of a user, so notifications can be sent in their
+/// language
+pub async fn remember_locale(pool: &PgPool, user: UserId, locale: &str) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+    INSERT INTO user_locales ("user", locale)
+    VALUES ($1, $2)
+    ON CONFLICT ("user") DO UPDATE
+    SET locale = EXCLUDED.locale
+    WHERE user_locales.locale <> EXCLUDED.locale
+    "#,
+        user.0.to_string(),
+        locale,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn user_locale(pool: &PgPool, user: UserId) -> sqlx::Result<Option<String>> {
+    sqlx::query_scalar!(
+        r#"SELECT locale FROM user_locales WHERE "user" = $1"#,
+        user.0.to_string()
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn stored_templates(
+    pool: &PgPool,
+    guild: GuildId,
+) -> sqlx::Result<HashMap<String, Template>> {
+    Ok(sqlx::query!(
+        r#"SELECT locale, title, body, footer, appeal FROM sanction_templates WHERE guild = $1"#,
+        guild.0.to_string()
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.locale,
+            Template {
+                title: row.title,
+                body: row.body,
+                footer: row.footer,
+                appeal: row.appeal,
+            },
+        )
+    })
+    .collect())
+}
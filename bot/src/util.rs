@@ -1,40 +1,257 @@
-use chrono::{DateTime, Utc};
-use poise::serenity_prelude::{Color, Context, Message, Result, User};
+use std::fmt::{self, Display};
+
+use chrono::{DateTime, Duration, Utc};
+use poise::serenity_prelude::{
+    ActionRowComponent, CacheHttp, ChannelId, Color, Context, CreateMessage, GuildId, HttpError,
+    ModalSubmitInteractionData, Result, SerenityError, User,
+};
+use tracing::Level;
+
+use crate::{
+    appeal::Appeals,
+    modlog::{Case, Entry, ModLog},
+    template::{Placeholders, Template},
+    Data,
+};
+
+/// Discord's error code for a member that isn't in the guild (anymore)
+pub const UNKNOWN_MEMBER: isize = 10007;
+/// Discord's error code for a user that isn't banned
+pub const UNKNOWN_BAN: isize = 10026;
+
+/// Whether Discord rejected a request with the JSON error `code`
+pub fn is_discord_error(error: &SerenityError, code: isize) -> bool {
+    match error {
+        SerenityError::Http(e) => {
+            matches!(&**e, HttpError::UnsuccessfulRequest(r) if r.error.code == code)
+        }
+        _ => false,
+    }
+}
 
 #[allow(dead_code)]
 pub enum Penalty {
+    Warning,
     Timeout(DateTime<Utc>),
     Ban(Option<DateTime<Utc>>),
 }
 
+/// How a sanction notification reached the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    DirectMessage,
+    /// The user couldn't be reached by a direct message, so the notification
+    /// was posted to a private thread they were added to
+    Thread(ChannelId),
+    Failed,
+}
+
+impl Delivery {
+    /// The name this delivery is stored as in the database
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Delivery::DirectMessage => "dm",
+            Delivery::Thread(_) => "thread",
+            Delivery::Failed => "failed",
+        }
+    }
+}
+
+impl Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delivery::DirectMessage => f.write_str("✅ Direct message"),
+            Delivery::Thread(thread) => write!(f, "⚠️ Private thread <#{}>", thread),
+            Delivery::Failed => f.write_str("❌ Not delivered"),
+        }
+    }
+}
+
+/// Notify `user` about a sanction
+///
+/// The notification uses the template of `guild` for the locale of the user.
+/// If `case` is set and the guild accepts appeals, the notification comes
+/// with a button to appeal the case.
+///
+/// If the user doesn't accept direct messages, the notification is posted to
+/// a private thread in `fallback`. How the notification was delivered is
+/// recorded on the case.
+#[allow(clippy::too_many_arguments)]
 pub async fn send_sanction_notification<S>(
     ctx: &Context,
+    data: &Data,
+    guild: GuildId,
+    user: &User,
+    reason: S,
+    penalty: Penalty,
+    case: Option<&Case>,
+    fallback: Option<ChannelId>,
+) -> Delivery
+where
+    S: ToString,
+{
+    let delivery = deliver(ctx, data, guild, user, reason, penalty, case, fallback).await;
+    if let Some(case) = case {
+        ModLog::record_delivery(ctx, data, case, delivery).await;
+    }
+    delivery
+}
+
+/// Notify `user` about the ban in `entry` and ban them
+///
+/// The notification is sent before the ban, since the bot can't send direct
+/// messages to a user it shares no guild with anymore. Its appeal button
+/// refers to a reserved case number, the case itself is only stored once the
+/// ban succeeded.
+pub async fn ban_with_notification(
+    ctx: &Context,
+    data: &Data,
+    user: &User,
+    entry: Entry,
+    delete_days: u8,
+) -> Result<Option<Case>> {
+    let guild = entry.guild;
+    let reserved = ModLog::reserve(data).await.map(|id| Case {
+        id,
+        entry: entry.clone(),
+        message: None,
+    });
+    let delivery = deliver(
+        ctx,
+        data,
+        guild,
+        user,
+        entry.reason.as_deref().unwrap_or("No reason given"),
+        Penalty::Ban(None),
+        reserved.as_ref(),
+        // the user loses access to the thread with the ban
+        None,
+    )
+    .await;
+
+    match &entry.reason {
+        Some(reason) => {
+            guild
+                .ban_with_reason(ctx.http(), user.id, delete_days, reason)
+                .await?
+        }
+        None => guild.ban(ctx.http(), user.id, delete_days).await?,
+    }
+
+    let case = match reserved {
+        Some(reserved) => ModLog::log_reserved(ctx, data, reserved.id, entry).await,
+        None => ModLog::log(ctx, data, entry).await,
+    };
+    if let Some(case) = &case {
+        ModLog::record_delivery(ctx, data, case, delivery).await;
+    }
+    Ok(case)
+}
+
+/// Send the notification of [`send_sanction_notification`] without recording
+/// it on the case
+#[allow(clippy::too_many_arguments)]
+async fn deliver<S>(
+    ctx: &Context,
+    data: &Data,
+    guild: GuildId,
     user: &User,
     reason: S,
     penalty: Penalty,
-) -> Result<Message>
+    case: Option<&Case>,
+    fallback: Option<ChannelId>,
+) -> Delivery
 where
     S: ToString,
 {
-    user.direct_message(ctx, |f| {
-        f.add_embed(|e| {
-            e.title("You've been sanctioned");
-            e.color(Color::RED);
-            e.description(format!(
-                "Hey {username},\n
-                I'm sorry to tell you, but you've been sanctioned for {reason}.
-                Your penalty is {penalty}
-            ",
-                username = user.name,
-                reason = reason.to_string(),
-                penalty = gen_penalty_string(penalty)
-            ));
-            e.footer(|f| f.text("you probably deserved it"));
-            e
-        });
-        f
-    })
-    .await
+    let appeal = case.filter(|_| data.guild(guild).appeals.is_some());
+    let template = Template::resolve(ctx, data, guild, user.id).await;
+    let placeholders = Placeholders {
+        username: user.name.clone(),
+        reason: reason.to_string(),
+        penalty: gen_penalty_string(penalty),
+        guild: guild.name(ctx).unwrap_or_default(),
+        appeal: match appeal {
+            Some(_) => template.appeal_text().to_string(),
+            None => String::new(),
+        },
+    };
+    let notification = template.render(&placeholders).unwrap_or_else(|e| {
+        event!(Level::WARN, guild = guild.0, error = ?e, "broken sanction template in guild {}: {}", guild.0, e);
+        Template::fallback()
+            .render(&placeholders)
+            .expect("fallback template is valid")
+    });
+
+    match user
+        .direct_message(ctx, |m| notification_message(m, &notification, appeal))
+        .await
+    {
+        Ok(_) => Delivery::DirectMessage,
+        Err(e) => {
+            event!(Level::INFO, user = user.id.0, error = ?e, "cannot send direct message to user {}: {}", user.id.0, e);
+            match fallback {
+                Some(channel) => {
+                    match notify_in_thread(ctx, channel, user, &notification, appeal).await {
+                        Ok(thread) => Delivery::Thread(thread),
+                        Err(e) => {
+                            event!(Level::WARN, user = user.id.0, channel = channel.0, error = ?e, "cannot notify user {} in a private thread: {}", user.id.0, e);
+                            Delivery::Failed
+                        }
+                    }
+                }
+                None => Delivery::Failed,
+            }
+        }
+    }
+}
+
+/// Create a private thread in `channel` that only `user` is added to and post
+/// the notification there
+async fn notify_in_thread(
+    ctx: &Context,
+    channel: ChannelId,
+    user: &User,
+    notification: &Template,
+    appeal: Option<&Case>,
+) -> Result<ChannelId> {
+    let thread = channel
+        .create_private_thread(ctx.http(), |t| {
+            t.name(format!("Sanction of {}", user.name))
+                .auto_archive_duration(1440)
+        })
+        .await?;
+    thread.id.add_thread_member(ctx.http(), user.id).await?;
+    thread
+        .id
+        .send_message(ctx.http(), |m| {
+            m.content(format!("<@{}>", user.id));
+            notification_message(m, notification, appeal)
+        })
+        .await?;
+    Ok(thread.id)
+}
+
+fn notification_message<'a, 'b>(
+    m: &'b mut CreateMessage<'a>,
+    notification: &Template,
+    appeal: Option<&Case>,
+) -> &'b mut CreateMessage<'a> {
+    m.add_embed(|e| {
+        if let Some(ref title) = notification.title {
+            e.title(title);
+        }
+        e.color(Color::RED);
+        e.description(notification.body.trim());
+        if let Some(ref footer) = notification.footer {
+            e.footer(|f| f.text(footer));
+        }
+        e
+    });
+    if let Some(case) = appeal {
+        m.components(|c| c.create_action_row(|r| r.add_button(Appeals::button(case.id))));
+    }
+    m
 }
 
 /// Generate a human readable penalty
@@ -46,6 +263,7 @@ fn gen_penalty_string(penalty: Penalty) -> String {
     let mut sb = String::new();
     sb.push_str("a ");
     match penalty {
+        Penalty::Warning => sb.push_str("warning"),
         Penalty::Timeout(x) => {
             sb.push_str(format!("timeout until <t:{}:R>", x.timestamp()).as_str())
         }
@@ -59,3 +277,81 @@ fn gen_penalty_string(penalty: Penalty) -> String {
     };
     sb
 }
+
+/// Generate a short human readable duration
+///
+/// This puts out a string like `1d 2h 30min`. Units that are zero are left
+/// out.
+pub fn format_duration(mut duration: Duration) -> String {
+    let days = match duration.num_days() {
+        0 => None,
+        x => {
+            duration -= Duration::days(x);
+            Some(format!("{}d", x))
+        }
+    };
+    let hours = match duration.num_hours() {
+        0 => None,
+        x => {
+            duration -= Duration::hours(x);
+            Some(format!("{}h", x))
+        }
+    };
+    let minutes = match duration.num_minutes() {
+        0 => None,
+        x => {
+            duration -= Duration::minutes(x);
+            Some(format!("{}min", x))
+        }
+    };
+    let seconds = match duration.num_seconds() {
+        0 => None,
+        x => Some(format!("{}s", x)),
+    };
+
+    format!(
+        "{} {} {} {}",
+        days.unwrap_or_default(),
+        hours.unwrap_or_default(),
+        minutes.unwrap_or_default(),
+        seconds.unwrap_or_default(),
+    )
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// The channel, its parent channel (for threads) and its category
+pub fn channel_scopes(ctx: &Context, channel: ChannelId) -> Vec<ChannelId> {
+    let mut scopes = vec![channel];
+    let mut current = channel;
+    // a thread is in a channel, which is in a category
+    for _ in 0..2 {
+        match current
+            .to_channel_cached(&ctx.cache)
+            .and_then(|c| c.guild())
+            .and_then(|c| c.parent_id)
+        {
+            Some(parent) => {
+                scopes.push(parent);
+                current = parent;
+            }
+            None => break,
+        }
+    }
+    scopes
+}
+
+/// Get the value of the text input `custom_id` from a submitted modal
+pub fn modal_text(data: &ModalSubmitInteractionData, custom_id: &str) -> Option<String> {
+    data.components
+        .iter()
+        .flat_map(|row| row.components.iter())
+        .find_map(|component| match component {
+            ActionRowComponent::InputText(text) if text.custom_id == custom_id => {
+                Some(text.value.clone())
+            }
+            _ => None,
+        })
+        .filter(|value| !value.is_empty())
+}
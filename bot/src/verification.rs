@@ -0,0 +1,443 @@
+//! Member verification
+//!
+//! New members get the unverified role and a message with a button in the
+//! verification channel. The button opens a modal with a small math
+//! challenge; members who solve it get the member role, members who don't
+//! solve it in time are kicked or quarantined. Their invite only counts for
+//! the inviter once they verified.
+
+use chrono::{DateTime, Duration, Utc};
+use poise::serenity_prelude::{
+    ButtonStyle, CacheHttp, ChannelId, Color, Context, GuildId, InputTextStyle,
+    InteractionResponseType, Member, MessageComponentInteraction, MessageId,
+    ModalSubmitInteraction, RoleId, UserId,
+};
+use rand::Rng;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::Level;
+
+use crate::{quarantine::Remedy, util::modal_text, Data, Result};
+
+/// Button on the verification message that opens the challenge
+const VERIFY: &str = "verify";
+/// Modal a member submits the answer to the challenge with
+const VERIFY_SUBMIT: &str = "verify-submit";
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct VerificationConfig {
+    /// The role new members have until they verified
+    #[serde_as(as = "DisplayFromStr")]
+    pub unverified: RoleId,
+    /// The role members get once they verified
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub member: Option<RoleId>,
+    /// The channel the verification messages are posted in
+    #[serde_as(as = "DisplayFromStr")]
+    pub channel: ChannelId,
+    /// Members who don't verify within this many seconds are removed
+    #[serde(default = "default_timeout")]
+    pub timeout: i64,
+    /// What happens to members who don't verify in time
+    #[serde(default)]
+    pub remedy: Remedy,
+}
+
+fn default_timeout() -> i64 {
+    10 * 60
+}
+
+pub struct Verification;
+
+impl Verification {
+    /// Whether a component or modal with this id belongs to a verification
+    pub fn handles(custom_id: &str) -> bool {
+        custom_id.starts_with(VERIFY)
+    }
+
+    /// Ask a new member to verify
+    #[instrument(skip_all, name = "verification_join", level = "debug")]
+    pub async fn on_join(ctx: &Context, data: &Data, member: &Member) {
+        let guild = member.guild_id;
+        let config = match data.guild(guild).verification {
+            Some(config) if !member.user.bot => config,
+            _ => return,
+        };
+        if let Err(e) = Self::start(ctx, data, &config, guild, member.user.id).await {
+            event!(Level::WARN, member = member.user.id.0, guild = guild.0, error = ?e, "failed to start verification of member {}: {}", member.user.id.0, e);
+        }
+    }
+
+    async fn start(
+        ctx: &Context,
+        data: &Data,
+        config: &VerificationConfig,
+        guild: GuildId,
+        user: UserId,
+    ) -> Result<()> {
+        ctx.http
+            .add_member_role(guild.0, user.0, config.unverified.0, Some("Verification"))
+            .await?;
+
+        let expires_at = Utc::now() + Duration::seconds(config.timeout);
+        let message = config
+            .channel
+            .send_message(ctx.http(), |m| {
+                m.content(format!("<@{}>", user));
+                m.allowed_mentions(|a| a.users(vec![user]));
+                m.embed(|e| {
+                    e.title("Welcome!");
+                    e.color(Color::BLURPLE);
+                    e.description(format!(
+                        "Please press the button below to verify that you're human. If you don't \
+                         verify <t:{}:R>, you'll be {}.",
+                        expires_at.timestamp(),
+                        match config.remedy {
+                            Remedy::Kick => "kicked",
+                            Remedy::Quarantine => "quarantined",
+                        }
+                    ))
+                });
+                m.components(|c| {
+                    c.create_action_row(|row| {
+                        row.create_button(|b| {
+                            b.custom_id(format!("{}:{}", VERIFY, user))
+                                .label("Verify")
+                                .style(ButtonStyle::Success)
+                        })
+                    })
+                })
+            })
+            .await;
+        let message = match message {
+            Ok(message) => Some(message.id),
+            Err(e) => {
+                event!(Level::WARN, channel = config.channel.0, error = ?e, "failed to post verification message: {}", e);
+                None
+            }
+        };
+
+        let (question, answer) = challenge();
+        sqlx::query!(
+            r#"
+        INSERT INTO pending_verifications (guild, "user", question, answer, channel, message, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (guild, "user") DO UPDATE
+        SET question = EXCLUDED.question,
+        answer = EXCLUDED.answer,
+        channel = EXCLUDED.channel,
+        message = EXCLUDED.message,
+        expires_at = EXCLUDED.expires_at
+        "#,
+            guild.0.to_string(),
+            user.0.to_string(),
+            question,
+            answer,
+            config.channel.0.to_string(),
+            message.map(|m| m.0.to_string()),
+            expires_at,
+        )
+        .execute(&data.pool)
+        .await?;
+
+        Self::schedule(ctx, data, guild, user, expires_at);
+        Ok(())
+    }
+
+    /// Schedule the kicks of all members of `guilds` who haven't verified yet,
+    /// e.g. after a restart
+    #[instrument(skip_all, name = "verification_resume", level = "debug")]
+    pub async fn resume(ctx: &Context, data: &Data, guilds: &[GuildId]) {
+        let guilds = guilds.iter().map(|g| g.0.to_string()).collect::<Vec<_>>();
+        let pending = match sqlx::query!(
+            r#"
+        SELECT guild, "user", expires_at FROM pending_verifications
+        WHERE guild = ANY($1)
+        "#,
+            &guilds
+        )
+        .fetch_all(&data.pool)
+        .await
+        {
+            Ok(pending) => pending,
+            Err(e) => {
+                event!(Level::WARN, error = ?e, "failed to load pending verifications: {}", e);
+                return;
+            }
+        };
+        for row in pending {
+            match (row.guild.parse(), row.user.parse()) {
+                (Ok(guild), Ok(user)) => Self::schedule(ctx, data, guild, user, row.expires_at),
+                _ => event!(
+                    Level::WARN,
+                    "invalid pending verification of {} in {}",
+                    row.user,
+                    row.guild
+                ),
+            }
+        }
+    }
+
+    fn schedule(ctx: &Context, data: &Data, guild: GuildId, user: UserId, at: DateTime<Utc>) {
+        let ctx = ctx.clone();
+        let data = data.clone();
+        tokio::spawn(async move {
+            // the verification is resumed on the next start if the bot shuts
+            // down in the meantime
+            let duration = (at - Utc::now()).to_std().unwrap_or_default();
+            if data.shutdown.sleep(duration).await {
+                let _work = data.shutdown.work();
+                Self::expire(&ctx, &data, guild, user).await;
+            }
+        });
+    }
+
+    /// Kick or quarantine a member if they still haven't verified
+    async fn expire(ctx: &Context, data: &Data, guild: GuildId, user: UserId) {
+        let pending = match sqlx::query!(
+            r#"
+        DELETE FROM pending_verifications
+        WHERE guild = $1 AND "user" = $2 AND expires_at <= now()
+        RETURNING channel, message
+        "#,
+            guild.0.to_string(),
+            user.0.to_string(),
+        )
+        .fetch_optional(&data.pool)
+        .await
+        {
+            Ok(Some(pending)) => pending,
+            // verified in the meantime, or rejoined and got a new deadline
+            Ok(None) => return,
+            Err(e) => {
+                event!(Level::WARN, member = user.0, error = ?e, "failed to load pending verification of {}: {}", user.0, e);
+                return;
+            }
+        };
+        delete_message(ctx, pending.channel, pending.message).await;
+
+        let reason = "Didn't verify in time";
+        let remedy = data
+            .guild(guild)
+            .verification
+            .map(|c| c.remedy)
+            .unwrap_or_default();
+        if data.in_maintenance(guild) {
+            data.maintenance
+                .defer(&data.pool, guild, user, &remedy.to_string(), reason)
+                .await;
+            return;
+        }
+        if let Err(e) = remedy
+            .apply(ctx, data, guild, user, ctx.cache.current_user_id(), reason)
+            .await
+        {
+            event!(Level::INFO, member = user.0, error = ?e, "failed to {} unverified member {}: {}", remedy, user.0, e)
+        }
+    }
+
+    #[instrument(skip_all, name = "verification_component", level = "debug")]
+    pub async fn on_component(
+        ctx: &Context,
+        data: &Data,
+        interaction: &MessageComponentInteraction,
+    ) {
+        if let Err(e) = Self::open_challenge(ctx, data, interaction).await {
+            event!(Level::DEBUG, error = ?e, "failed to handle verification component {}: {}", interaction.data.custom_id, e);
+            if let Err(e) = interaction
+                .create_interaction_response(ctx.http(), |r| {
+                    r.interaction_response_data(|d| d.content(e.to_string()).ephemeral(true))
+                })
+                .await
+            {
+                event!(Level::WARN, error = ?e, "failed to respond to verification component: {}", e);
+            }
+        }
+    }
+
+    #[instrument(skip_all, name = "verification_modal", level = "debug")]
+    pub async fn on_modal(ctx: &Context, data: &Data, interaction: &ModalSubmitInteraction) {
+        if let Err(e) = Self::submit(ctx, data, interaction).await {
+            event!(Level::DEBUG, error = ?e, "failed to handle verification modal {}: {}", interaction.data.custom_id, e);
+            if let Err(e) = interaction
+                .create_interaction_response(ctx.http(), |r| {
+                    r.interaction_response_data(|d| d.content(e.to_string()).ephemeral(true))
+                })
+                .await
+            {
+                event!(Level::WARN, error = ?e, "failed to respond to verification modal: {}", e);
+            }
+        }
+    }
+
+    /// Show the challenge to the member the button belongs to
+    async fn open_challenge(
+        ctx: &Context,
+        data: &Data,
+        interaction: &MessageComponentInteraction,
+    ) -> Result<()> {
+        let user = parse_custom_id(&interaction.data.custom_id, VERIFY)
+            .ok_or_else(|| anyhow!("Invalid verification button."))?;
+        if user != interaction.user.id {
+            return Err(anyhow!("This isn't your verification.").into());
+        }
+        let guild = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Verification only works in servers."))?;
+        let question = sqlx::query_scalar!(
+            r#"SELECT question FROM pending_verifications WHERE guild = $1 AND "user" = $2"#,
+            guild.0.to_string(),
+            user.0.to_string(),
+        )
+        .fetch_optional(&data.pool)
+        .await?
+        .ok_or_else(|| anyhow!("You're already verified."))?;
+
+        interaction
+            .create_interaction_response(ctx.http(), |r| {
+                r.kind(InteractionResponseType::Modal)
+                    .interaction_response_data(|d| {
+                        d.custom_id(format!("{}:{}", VERIFY_SUBMIT, user))
+                            .title("Verification")
+                            .components(|c| {
+                                c.create_action_row(|row| {
+                                    row.create_input_text(|t| {
+                                        t.custom_id("answer")
+                                            .label(question)
+                                            .style(InputTextStyle::Short)
+                                            .max_length(10)
+                                            .required(true)
+                                    })
+                                })
+                            })
+                    })
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Check the answer and verify the member if it is right
+    async fn submit(
+        ctx: &Context,
+        data: &Data,
+        interaction: &ModalSubmitInteraction,
+    ) -> Result<()> {
+        let user = parse_custom_id(&interaction.data.custom_id, VERIFY_SUBMIT)
+            .ok_or_else(|| anyhow!("Invalid verification."))?;
+        if user != interaction.user.id {
+            return Err(anyhow!("This isn't your verification.").into());
+        }
+        let guild = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Verification only works in servers."))?;
+        let config = data
+            .guild(guild)
+            .verification
+            .ok_or_else(|| anyhow!("This server doesn't use verification anymore."))?;
+        let pending = sqlx::query!(
+            r#"SELECT answer, channel, message FROM pending_verifications WHERE guild = $1 AND "user" = $2"#,
+            guild.0.to_string(),
+            user.0.to_string(),
+        )
+        .fetch_optional(&data.pool)
+        .await?
+        .ok_or_else(|| anyhow!("You're already verified."))?;
+
+        let answer = modal_text(&interaction.data, "answer").unwrap_or_default();
+        if answer.trim() != pending.answer {
+            // a new question for every try, so it can't be brute forced
+            let (question, answer) = challenge();
+            sqlx::query!(
+                r#"UPDATE pending_verifications SET question = $1, answer = $2 WHERE guild = $3 AND "user" = $4"#,
+                question,
+                answer,
+                guild.0.to_string(),
+                user.0.to_string(),
+            )
+            .execute(&data.pool)
+            .await?;
+            return Err(
+                anyhow!("That's not right. Press the button again to get a new question.").into(),
+            );
+        }
+
+        ctx.http
+            .remove_member_role(guild.0, user.0, config.unverified.0, Some("Verified"))
+            .await?;
+        if let Some(role) = config.member {
+            ctx.http
+                .add_member_role(guild.0, user.0, role.0, Some("Verified"))
+                .await?;
+        }
+        sqlx::query!(
+            r#"DELETE FROM pending_verifications WHERE guild = $1 AND "user" = $2"#,
+            guild.0.to_string(),
+            user.0.to_string(),
+        )
+        .execute(&data.pool)
+        .await?;
+        sqlx::query!(
+            r#"UPDATE invited_members SET confirmed = TRUE WHERE guild = $1 AND "user" = $2"#,
+            guild.0.to_string(),
+            user.0.to_string(),
+        )
+        .execute(&data.pool)
+        .await?;
+        event!(
+            Level::INFO,
+            member = user.0,
+            guild = guild.0,
+            "member {} verified in guild {}",
+            user.0,
+            guild.0
+        );
+
+        interaction
+            .create_interaction_response(ctx.http(), |r| {
+                r.interaction_response_data(|d| {
+                    d.content("You're verified, welcome!").ephemeral(true)
+                })
+            })
+            .await?;
+        delete_message(ctx, pending.channel, pending.message).await;
+        Ok(())
+    }
+}
+
+/// A math question and its answer
+fn challenge() -> (String, String) {
+    let mut rng = rand::thread_rng();
+    let (a, b) = (rng.gen_range(1..=20), rng.gen_range(1..=20));
+    match rng.gen_bool(0.5) {
+        true => (format!("What is {} plus {}?", a, b), (a + b).to_string()),
+        false => (
+            format!("What is {} minus {}?", a.max(b), a.min(b)),
+            (a.max(b) - a.min(b)).to_string(),
+        ),
+    }
+}
+
+/// Parse `kind:user`
+fn parse_custom_id(custom_id: &str, kind: &str) -> Option<UserId> {
+    let (k, user) = custom_id.split_once(':')?;
+    match k == kind {
+        true => user.parse().ok(),
+        false => None,
+    }
+}
+
+async fn delete_message(ctx: &Context, channel: Option<String>, message: Option<String>) {
+    let (channel, message) = match (channel, message) {
+        (Some(channel), Some(message)) => (channel, message),
+        _ => return,
+    };
+    let (channel, message): (ChannelId, MessageId) = match (channel.parse(), message.parse()) {
+        (Ok(channel), Ok(message)) => (channel, message),
+        _ => return,
+    };
+    if let Err(e) = channel.delete_message(ctx.http(), message).await {
+        event!(Level::DEBUG, message = message.0, error = ?e, "failed to delete verification message {}: {}", message.0, e);
+    }
+}
@@ -0,0 +1,23 @@
+-- `id`: The number of the case
+-- `guild`: The guild the action was taken in
+-- `action`: What was done, e.g. `timeout` or `ban`
+-- `target`: The user the action was taken against
+-- `moderator`: The user who took the action. This is the bot itself for
+--   automatic actions
+-- `reason`: Why the action was taken
+-- `expires_at`: When the penalty ends, if it's temporary
+-- `created_at`: time the action was taken
+-- `log_channel`: The channel the case was posted to
+-- `log_message`: The message the case was posted as
+CREATE TABLE cases(
+    "id" BIGSERIAL PRIMARY KEY,
+    "guild" TEXT NOT NULL,
+    "action" TEXT NOT NULL,
+    "target" TEXT NOT NULL,
+    "moderator" TEXT NOT NULL,
+    "reason" TEXT,
+    "expires_at" TIMESTAMPTZ,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
+    "log_channel" TEXT,
+    "log_message" TEXT
+)
@@ -0,0 +1,20 @@
+-- `id`: The number of the appeal
+-- `case`: The case that is appealed. Every case can only be appealed once
+-- `user`: The user who appealed
+-- `content`: Why the user thinks the case should be reversed
+-- `status`: `pending`, `accepted` or `denied`
+-- `staff`: The member of the staff who decided on the appeal
+-- `response`: The response of the staff to a denied appeal
+-- `created_at`: time the appeal was submitted
+-- `decided_at`: time the appeal was accepted or denied
+CREATE TABLE appeals(
+    "id" BIGSERIAL PRIMARY KEY,
+    "case" BIGINT NOT NULL UNIQUE REFERENCES cases("id"),
+    "user" TEXT NOT NULL,
+    "content" TEXT NOT NULL,
+    "status" TEXT NOT NULL DEFAULT 'pending',
+    "staff" TEXT,
+    "response" TEXT,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
+    "decided_at" TIMESTAMPTZ
+)
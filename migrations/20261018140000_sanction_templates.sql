@@ -0,0 +1,18 @@
+-- `guild`: The guild this template is used in
+-- `locale`: The Discord locale of the template, e.g. `en-US` or `de`
+-- `title`, `body`, `footer`: The parts of the notification embed
+CREATE TABLE sanction_templates(
+    "guild" TEXT NOT NULL,
+    "locale" TEXT NOT NULL,
+    "title" TEXT,
+    "body" TEXT NOT NULL,
+    "footer" TEXT,
+    PRIMARY KEY("guild", "locale")
+);
+
+-- `user`: The user
+-- `locale`: The Discord locale of the last interaction of the user
+CREATE TABLE user_locales(
+    "user" TEXT PRIMARY KEY,
+    "locale" TEXT NOT NULL
+)
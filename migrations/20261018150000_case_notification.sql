@@ -0,0 +1,4 @@
+-- `notification`: How the user was notified about the case. `dm` for a direct
+--   message, `thread` for a private thread and `failed` if the user couldn't
+--   be notified. NULL if no notification was sent.
+ALTER TABLE cases ADD COLUMN "notification" TEXT
@@ -0,0 +1,13 @@
+-- `guild`: The guild the offence happened in
+-- `user`: The user who committed the offence
+-- `rule`: The rule that was broken, e.g. `sticker`
+-- `created_at`: time of the offence
+CREATE TABLE offences(
+    "id" BIGSERIAL PRIMARY KEY,
+    "guild" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "rule" TEXT NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX offences_guild_user_rule ON offences("guild", "user", "rule", "created_at")
@@ -0,0 +1,20 @@
+-- `guild`: The guild the filter belongs to
+-- `kind`: `regex` or `words`
+-- `pattern`: A regular expression or a comma separated list of words
+-- `action`: What happens if a message matches, e.g. `delete`
+-- `reason`: Shown to the user and in the mod log
+-- `scope`: The channel or category the filter applies to, all channels if NULL
+-- `created_by`: The user who added the filter
+CREATE TABLE filters(
+    "id" BIGSERIAL PRIMARY KEY,
+    "guild" TEXT NOT NULL,
+    "kind" TEXT NOT NULL,
+    "pattern" TEXT NOT NULL,
+    "action" TEXT NOT NULL,
+    "reason" TEXT NOT NULL,
+    "scope" TEXT,
+    "created_by" TEXT NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX filters_guild ON filters("guild")
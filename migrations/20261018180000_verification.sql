@@ -0,0 +1,20 @@
+-- `confirmed`: Whether the member passed the verification. Only confirmed
+-- members count towards the stats of the inviter.
+ALTER TABLE invited_members ADD COLUMN "confirmed" BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- `guild`: The guild the member has to verify in
+-- `user`: The member who has to verify
+-- `question`: The challenge the member has to solve
+-- `answer`: The expected answer
+-- `channel`, `message`: The verification message
+-- `expires_at`: When the member is kicked if they didn't verify
+CREATE TABLE pending_verifications(
+    "guild" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "question" TEXT NOT NULL,
+    "answer" TEXT NOT NULL,
+    "channel" TEXT,
+    "message" TEXT,
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY("guild", "user")
+)
@@ -0,0 +1,14 @@
+-- `guild`: The guild the member is quarantined in
+-- `user`: The quarantined member
+-- `roles`: The roles the member had before the quarantine
+-- `reason`: Why the member was quarantined
+-- `moderator`: Who quarantined the member
+CREATE TABLE quarantined_members(
+    "guild" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "roles" TEXT[] NOT NULL,
+    "reason" TEXT,
+    "moderator" TEXT NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY("guild", "user")
+)
@@ -0,0 +1,15 @@
+-- Settings of a guild that were changed with `/config`. They take precedence
+-- over the settings in the config file.
+--
+-- `guild`: The guild the setting belongs to
+-- `key`: The name of the setting, e.g. `modlog`
+-- `value`: The value of the setting, IDs are stored without a mention
+-- `updated_by`: The user who last changed the setting
+CREATE TABLE guild_settings(
+    "guild" TEXT NOT NULL,
+    "key" TEXT NOT NULL,
+    "value" TEXT NOT NULL,
+    "updated_by" TEXT NOT NULL,
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY("guild", "key")
+)
@@ -0,0 +1,14 @@
+-- `id`: The number of the deferred action
+-- `guild`: The guild the action would have been taken in
+-- `user`: The user the action would have been taken against
+-- `action`: What would have been done, e.g. `kick` or `timeout`
+-- `reason`: Why the action would have been taken
+-- `created_at`: time the action was held back
+CREATE TABLE deferred_actions(
+    "id" BIGSERIAL PRIMARY KEY,
+    "guild" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "action" TEXT NOT NULL,
+    "reason" TEXT NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT now()
+)
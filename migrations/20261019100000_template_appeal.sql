@@ -0,0 +1,3 @@
+-- `appeal`: What the `{appeal}` placeholder is replaced with, in the language
+-- of the template
+ALTER TABLE sanction_templates ADD COLUMN "appeal" TEXT
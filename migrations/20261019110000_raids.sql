@@ -0,0 +1,18 @@
+-- `guild`: The guild in raid mode
+-- `reason`: Why raid mode was started
+-- `started_at`: When raid mode was started
+-- `last_join`: The last join during the raid, raid mode ends once no one
+-- joined for the cooldown
+-- `joins`: How many members joined during the raid
+-- `previous_verification`: The verification level before the raid, if raid
+-- mode raised it
+-- `paused_invites`: Whether raid mode paused the invites of the guild
+CREATE TABLE raids(
+    "guild" TEXT PRIMARY KEY,
+    "reason" TEXT NOT NULL,
+    "started_at" TIMESTAMPTZ NOT NULL,
+    "last_join" TIMESTAMPTZ NOT NULL,
+    "joins" BIGINT NOT NULL,
+    "previous_verification" SMALLINT,
+    "paused_invites" BOOLEAN NOT NULL
+)